@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use rand::RngCore;
+
+/// The stages of an atomic write, in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Create,
+    Permissions,
+    Write,
+    Sync,
+    Rename,
+    SyncDir,
+}
+
+/// Replace the file at `path` with `contents` so that a crash at any point leaves either
+/// the old file or the new one, never a partial write. The contents go to a temporary
+/// file in the same directory, which is flushed to disk and renamed over the original.
+pub fn write(path: impl AsRef<Path>, contents: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let temp_path = temp_path(path);
+
+    let result = write_temp(path, &temp_path, contents);
+    if result.is_err() {
+        // Nothing has replaced the original yet, so clean up and leave it alone
+        let _ = fs::remove_file(&temp_path);
+        return result;
+    }
+
+    if let Err(e) = check(Step::Rename).and_then(|_| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    // Make the rename itself durable
+    check(Step::SyncDir)?;
+    sync_dir(path)
+}
+
+fn write_temp(path: &Path, temp_path: &Path, contents: &[u8]) -> io::Result<()> {
+    check(Step::Create)?;
+    let mut file = create_new(temp_path)?;
+
+    check(Step::Permissions)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+
+    check(Step::Write)?;
+    file.write_all(contents)?;
+
+    check(Step::Sync)?;
+    file.sync_all()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{file_name}.{:016x}.tmp", rand::thread_rng().next_u64()))
+}
+
+#[cfg(unix)]
+fn create_new(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // Only the owner should be able to read a new database, existing ones keep their
+    // permissions
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened for syncing on Windows, the rename is durable once it returns
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(test))]
+fn check(_step: Step) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+thread_local! {
+    static FAIL_AT: std::cell::Cell<Option<Step>> = const { std::cell::Cell::new(None) };
+}
+
+// Tests can make a write fail at any step to check what is left behind
+#[cfg(test)]
+fn check(step: Step) -> io::Result<()> {
+    if FAIL_AT.with(|fail_at| fail_at.get()) == Some(step) {
+        return Err(io::Error::other(format!("simulated failure at {step:?}")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn fail_at(step: Option<Step>) {
+    FAIL_AT.with(|fail_at| fail_at.set(step));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn write_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.oxd");
+        write(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(dir_entries(dir.path()), vec!["test.oxd"]);
+    }
+
+    #[test]
+    fn write_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.oxd");
+        fs::write(&path, b"old contents").unwrap();
+        write(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(dir_entries(dir.path()), vec!["test.oxd"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.oxd");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write(&path, b"new").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+
+        let new_path = dir.path().join("new.oxd");
+        write(&new_path, b"new").unwrap();
+        assert_eq!(fs::metadata(&new_path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn failure_before_rename_keeps_old_file() {
+        for step in [Step::Create, Step::Permissions, Step::Write, Step::Sync, Step::Rename] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("test.oxd");
+            fs::write(&path, b"old").unwrap();
+
+            fail_at(Some(step));
+            let result = write(&path, b"new");
+            fail_at(None);
+
+            assert!(result.is_err(), "{step:?} should fail");
+            assert_eq!(fs::read(&path).unwrap(), b"old", "{step:?} damaged the old file");
+            assert_eq!(dir_entries(dir.path()), vec!["test.oxd"], "{step:?} left a temporary file");
+        }
+    }
+
+    #[test]
+    fn failure_after_rename_has_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.oxd");
+        fs::write(&path, b"old").unwrap();
+
+        fail_at(Some(Step::SyncDir));
+        let result = write(&path, b"new");
+        fail_at(None);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(dir_entries(dir.path()), vec!["test.oxd"]);
+    }
+}
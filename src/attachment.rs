@@ -0,0 +1,378 @@
+//! Files kept with an entry, like recovery codes or key files. Each one is encrypted into its
+//! own file in `<db_file>.attachments/`, so they never have to be held in memory or in the
+//! serialized database, which only records their ids, names and sizes.
+//!
+//! Files are encrypted in chunks with the STREAM construction: every chunk is sealed with
+//! XChaCha20-Poly1305 under a nonce made of a random prefix, the chunk's number and whether it
+//! is the last chunk, so chunks can't be reordered, dropped or cut off at the end.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::cipher::{CipherError, NONCE_LEN};
+
+/// The largest file that can be attached
+pub const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+const MAGIC: &[u8; 8] = b"OXPWATT1";
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+// The rest of each nonce is a big endian u32 chunk number and a byte marking the last chunk
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - 5;
+
+pub fn dir(file_path: &str) -> PathBuf {
+    PathBuf::from(format!("{file_path}.attachments"))
+}
+
+pub fn path(file_path: &str, id: &Uuid) -> PathBuf {
+    dir(file_path).join(id.to_string())
+}
+
+/// Encrypt everything read from `reader` into the file for attachment `id`, returning its
+/// size. Nothing is left behind if it fails or is larger than `max_size`.
+pub fn store(file_path: &str, key: &[u8], id: &Uuid, reader: impl Read, max_size: u64) -> Result<u64, AttachmentError> {
+    create_dir(&dir(file_path))?;
+    let final_path = path(file_path, id);
+    let temp_path = final_path.with_file_name(format!(".{id}.tmp"));
+
+    let result = create_new(&temp_path)
+        .map_err(AttachmentError::from)
+        .and_then(|file| encrypt(key, id, reader, file, max_size));
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        },
+    };
+    fs::rename(&temp_path, final_path)?;
+    Ok(size)
+}
+
+/// Decrypt the file for attachment `id` into `writer`, returning its size. If it fails part
+/// way through, what was written before the bad chunk has still been authenticated.
+pub fn extract(file_path: &str, key: &[u8], id: &Uuid, writer: impl Write) -> Result<u64, AttachmentError> {
+    let file = match File::open(path(file_path, id)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(AttachmentError::Missing(path(file_path, id).display().to_string())),
+        Err(e) => return Err(e.into()),
+    };
+    decrypt(key, id, file, writer)
+}
+
+/// Decrypt attachment `id` into the file at `output`. The file is only put in place once all of
+/// it has been authenticated, so a failure leaves whatever was there before. An existing file
+/// is only replaced with `replace`, which writes to devices like `/dev/null` directly.
+pub fn extract_to(file_path: &str, key: &[u8], id: &Uuid, output: &Path, replace: bool) -> Result<u64, AttachmentError> {
+    if replace && fs::metadata(output).is_ok_and(|metadata| !metadata.is_file()) {
+        let device = OpenOptions::new().write(true).open(output)?;
+        return extract(file_path, key, id, BufWriter::new(device));
+    }
+    // Claims the name, so a file made while this one is written isn't replaced either
+    if !replace {
+        create_new(output)?;
+    }
+
+    let temp_path = output.with_file_name(format!(".{id}.tmp"));
+    let result = create_new(&temp_path).map_err(AttachmentError::from).and_then(|mut file| {
+        let size = extract(file_path, key, id, BufWriter::new(&mut file))?;
+        file.sync_all()?;
+        fs::rename(&temp_path, output)?;
+        Ok(size)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        if !replace {
+            let _ = fs::remove_file(output);
+        }
+    }
+    result
+}
+
+// Attachments that are already gone are fine, there is nothing left to remove
+pub fn remove(file_path: &str, id: &Uuid) -> io::Result<()> {
+    match fs::remove_file(path(file_path, id)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Remove the files of attachments nothing in `referenced` refers to, like one stored just
+/// before a crash kept the database that lists it from being saved, or one that was still
+/// being written. Only files named like attachments are touched.
+pub fn sweep(file_path: &str, referenced: &HashSet<Uuid>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir(file_path)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let unreferenced = match name.strip_prefix('.').and_then(|name| name.strip_suffix(".tmp")) {
+            Some(id) => Uuid::parse_str(id).is_ok(),
+            None => Uuid::parse_str(&name).is_ok_and(|id| !referenced.contains(&id)),
+        };
+        if unreferenced {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// The name to save an attached file under, which can't lead out of the directory it is
+/// extracted into
+pub fn check_name(name: &str) -> Result<String, AttachmentError> {
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(AttachmentError::InvalidName(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+pub fn format_size(size: u64) -> String {
+    match size {
+        0..1024 => format!("{size} B"),
+        1024..1_048_576 => format!("{:.1} KiB", size as f64 / 1024.0),
+        _ => format!("{:.1} MiB", size as f64 / 1_048_576.0),
+    }
+}
+
+fn encrypt(key: &[u8], id: &Uuid, mut reader: impl Read, mut file: File, max_size: u64) -> Result<u64, AttachmentError> {
+    let aead = XChaCha20Poly1305::new_from_slice(key).map_err(|_| CipherError::InvalidKey)?;
+    let mut prefix = [0u8; NONCE_PREFIX_LEN];
+    rand::thread_rng().fill_bytes(&mut prefix);
+    file.write_all(MAGIC)?;
+    file.write_all(&prefix)?;
+
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    let mut size = 0;
+    for number in 0.. {
+        let read = read_full(&mut reader, &mut chunk)?;
+        size += read as u64;
+        if size > max_size {
+            return Err(AttachmentError::TooLarge(max_size));
+        }
+        // A short chunk is the last, a file that fills its last chunk ends with an empty one
+        let last = read < CHUNK_LEN;
+        let sealed = aead
+            .encrypt(&nonce(&prefix, number, last), Payload { msg: &chunk[..read], aad: id.as_bytes() })
+            .map_err(|_| CipherError::EncryptionFailure)?;
+        file.write_all(&sealed)?;
+        if last {
+            break;
+        }
+    }
+    file.sync_all()?;
+    Ok(size)
+}
+
+fn decrypt(key: &[u8], id: &Uuid, file: File, mut writer: impl Write) -> Result<u64, AttachmentError> {
+    let aead = XChaCha20Poly1305::new_from_slice(key).map_err(|_| CipherError::InvalidKey)?;
+    let mut reader = BufReader::new(file);
+    let mut header = [0u8; MAGIC.len() + NONCE_PREFIX_LEN];
+    if read_full(&mut reader, &mut header)? < header.len() || &header[..MAGIC.len()] != MAGIC {
+        return Err(AttachmentError::Malformed);
+    }
+    let prefix = &header[MAGIC.len()..];
+
+    let mut chunk = vec![0u8; CHUNK_LEN + TAG_LEN];
+    let mut size = 0;
+    for number in 0.. {
+        let read = read_full(&mut reader, &mut chunk)?;
+        let last = read < chunk.len() || reader.fill_buf()?.is_empty();
+        let plaintext = aead
+            .decrypt(&nonce(prefix, number, last), Payload { msg: &chunk[..read], aad: id.as_bytes() })
+            .map_err(|_| AttachmentError::AuthenticationFailure)?;
+        writer.write_all(&plaintext)?;
+        size += plaintext.len() as u64;
+        if last {
+            break;
+        }
+    }
+    writer.flush()?;
+    Ok(size)
+}
+
+fn nonce(prefix: &[u8], number: u32, last: bool) -> XNonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&number.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last.into();
+    *XNonce::from_slice(&nonce)
+}
+
+// Fill as much of `buffer` as there is left to read, unlike `read_exact` running out is fine
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(unix)]
+fn create_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn create_new(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[derive(Error, Debug)]
+pub enum AttachmentError {
+    #[error("failed to read or write the attachment: {0}")]
+    Io(#[from] io::Error),
+    #[error("the file is larger than {}, the most that can be attached", format_size(*.0))]
+    TooLarge(u64),
+    #[error("\"{0}\" can't be the name of an attachment, names can't be empty or contain slashes")]
+    InvalidName(String),
+    #[error("the attachment file {0} is missing, it may have been deleted or the database restored from a backup")]
+    Missing(String),
+    #[error("{0}")]
+    Cipher(#[from] CipherError),
+    #[error("the attachment file is not an oxidizepw attachment")]
+    Malformed,
+    #[error("the attachment failed authentication, it is corrupt, cut short or has been tampered with")]
+    AuthenticationFailure,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [5u8; 32];
+
+    fn round_trip(len: usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        let id = Uuid::new_v4();
+        let contents: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        assert_eq!(store(&file_path, &KEY, &id, contents.as_slice(), MAX_SIZE).unwrap(), len as u64);
+
+        let mut extracted = vec![];
+        assert_eq!(extract(&file_path, &KEY, &id, &mut extracted).unwrap(), len as u64);
+        assert_eq!(extracted, contents);
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        for len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN + 1, 3 * CHUNK_LEN] {
+            round_trip(len);
+        }
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        let id = Uuid::new_v4();
+        store(&file_path, &KEY, &id, vec![7u8; 2 * CHUNK_LEN + 10].as_slice(), MAX_SIZE).unwrap();
+        let sealed = fs::read(path(&file_path, &id)).unwrap();
+
+        // Cut off after the first chunk, which looks like the end of a shorter file
+        let first_chunk = MAGIC.len() + NONCE_PREFIX_LEN + CHUNK_LEN + TAG_LEN;
+        fs::write(path(&file_path, &id), &sealed[..first_chunk]).unwrap();
+        assert!(matches!(extract(&file_path, &KEY, &id, io::sink()), Err(AttachmentError::AuthenticationFailure)));
+
+        // Moved to another attachment's id
+        let other = Uuid::new_v4();
+        fs::write(path(&file_path, &other), &sealed).unwrap();
+        assert!(matches!(extract(&file_path, &KEY, &other, io::sink()), Err(AttachmentError::AuthenticationFailure)));
+
+        remove(&file_path, &id).unwrap();
+        assert!(matches!(extract(&file_path, &KEY, &id, io::sink()), Err(AttachmentError::Missing(_))));
+    }
+
+    #[test]
+    fn sweep_unreferenced() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        sweep(&file_path, &HashSet::new()).unwrap();
+
+        let (kept, orphaned) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [kept, orphaned] {
+            store(&file_path, &KEY, &id, b"codes".as_slice(), MAX_SIZE).unwrap();
+        }
+        fs::write(super::dir(&file_path).join(format!(".{}.tmp", Uuid::new_v4())), "").unwrap();
+        fs::write(super::dir(&file_path).join("notes.txt"), "").unwrap();
+        sweep(&file_path, &HashSet::from([kept])).unwrap();
+
+        let mut left: Vec<String> = fs::read_dir(super::dir(&file_path)).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec![kept.to_string(), "notes.txt".to_string()]);
+    }
+
+    #[test]
+    fn extract_to_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        let id = Uuid::new_v4();
+        store(&file_path, &KEY, &id, b"1234-5678\n".as_slice(), MAX_SIZE).unwrap();
+        let output = dir.path().join("codes.txt");
+        fs::write(&output, "mine").unwrap();
+
+        assert!(matches!(extract_to(&file_path, &KEY, &id, &output, false), Err(AttachmentError::Io(_))));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "mine");
+
+        // A file that fails authentication leaves the one it would have replaced alone
+        let mut sealed = fs::read(path(&file_path, &id)).unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        fs::write(path(&file_path, &id), &sealed).unwrap();
+        assert!(matches!(extract_to(&file_path, &KEY, &id, &output, true), Err(AttachmentError::AuthenticationFailure)));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "mine");
+        // Only the attachments directory and the original file are left
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        *sealed.last_mut().unwrap() ^= 1;
+        fs::write(path(&file_path, &id), &sealed).unwrap();
+        assert_eq!(extract_to(&file_path, &KEY, &id, &output, true).unwrap(), 10);
+        assert_eq!(fs::read_to_string(&output).unwrap(), "1234-5678\n");
+    }
+
+    #[test]
+    fn size_limit_and_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        let id = Uuid::new_v4();
+        let too_large = io::repeat(0).take(2 * CHUNK_LEN as u64 + 1);
+        assert!(matches!(store(&file_path, &KEY, &id, too_large, 2 * CHUNK_LEN as u64), Err(AttachmentError::TooLarge(_))));
+        assert_eq!(fs::read_dir(super::dir(&file_path)).unwrap().count(), 0);
+
+        assert_eq!(check_name(" codes.pdf ").unwrap(), "codes.pdf");
+        assert!(check_name("../id_rsa").is_err());
+        assert!(check_name("..").is_err());
+        assert_eq!(format_size(1536), "1.5 KiB");
+    }
+}
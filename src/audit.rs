@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::{
+    breach::{BreachError, BreachList},
+    password::{self, Password},
+    strength,
+};
+
+pub const DEFAULT_MAX_AGE_DAYS: u32 = 365;
+
+// Names and usernames shorter than this turn up inside unrelated passwords too often
+const MIN_CONTAINED_LEN: usize = 3;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Issue {
+    Empty,
+    Reused,
+    Weak,
+    ContainsName,
+    ContainsUsername,
+    Stale,
+    Breached,
+    // The breach list has no range file the password's hash would be in
+    NotChecked,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Issue::Empty => "empty",
+            Issue::Reused => "reused",
+            Issue::Weak => "weak",
+            Issue::ContainsName => "contains name",
+            Issue::ContainsUsername => "contains username",
+            Issue::Stale => "stale",
+            Issue::Breached => "breached",
+            Issue::NotChecked => "not checked",
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Finding {
+    pub id: String,
+    pub name: String,
+    pub username: String,
+    pub issue: Issue,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub entries: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// Check decrypted passwords for problems. Passwords older than `max_age` are stale,
+/// passwords saved before their age was recorded are never reported as stale. Each password
+/// is also looked up in `breaches` if a list of breached passwords is given.
+pub fn audit(passwords: &[Password], now: DateTime<Utc>, max_age: Duration, mut breaches: Option<&mut BreachList>) -> Result<Report, BreachError> {
+    let mut findings = vec![];
+
+    // Group equal passwords to find the ones shared between entries
+    let mut uses: HashMap<&str, Vec<&Password>> = HashMap::new();
+    for password in passwords.iter().filter(|password| !password.password.is_empty()) {
+        uses.entry(&password.password).or_default().push(password);
+    }
+
+    for password in passwords {
+        let mut report = |issue, detail: String| findings.push(Finding {
+            id: password.id.to_string(),
+            name: password.name.clone(),
+            username: password.username.clone(),
+            issue,
+            detail,
+        });
+
+        if password.password.is_empty() {
+            report(Issue::Empty, "no password is saved".to_string());
+            continue;
+        }
+
+        let others: Vec<&str> = uses[password.password.as_str()]
+            .iter()
+            .filter(|other| other.id != password.id)
+            .map(|other| other.name.as_str())
+            .collect();
+        if !others.is_empty() {
+            report(Issue::Reused, format!("also used by {}", others.join(", ")));
+        }
+
+        let strength = strength::estimate(&password.password, &[&password.name, &password.username]);
+        if strength.is_weak() {
+            report(Issue::Weak, strength.to_string());
+        }
+
+        let lowercase = password.password.to_lowercase();
+        for (issue, field) in [(Issue::ContainsName, &password.name), (Issue::ContainsUsername, &password.username)] {
+            if field.chars().count() >= MIN_CONTAINED_LEN && lowercase.contains(&field.to_lowercase()) {
+                report(issue, format!("the password contains \"{field}\""));
+            }
+        }
+
+        // A range directory with files missing can still check the other passwords
+        match breaches.as_deref_mut().map(|breaches| breaches.count(&password.password)) {
+            Some(Ok(0)) | None => (),
+            Some(Ok(count)) => report(Issue::Breached, format!("seen {count} times in data breaches")),
+            Some(Err(BreachError::MissingRange(prefix))) => report(Issue::NotChecked, format!("the breach list has no range file for {prefix}")),
+            Some(Err(e)) => return Err(e),
+        }
+
+        let changed = password.password_changed.as_deref().and_then(password::parse_timestamp);
+        if let Some(changed) = changed {
+            let age = now.signed_duration_since(changed);
+            if age > max_age {
+                report(Issue::Stale, format!("changed {} days ago", age.num_days()));
+            }
+        }
+    }
+
+    Ok(Report { entries: passwords.len(), findings })
+}
+
+// A table for people, `serde_json` gives the same report to scripts
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.findings.is_empty() {
+            return write!(f, "No problems found in {} passwords", self.entries);
+        }
+
+        let name_width = self.findings.iter().map(|finding| finding.name.chars().count()).max().unwrap_or(0).max(4);
+        let issue_width = self.findings.iter().map(|finding| finding.issue.to_string().len()).max().unwrap_or(0).max(5);
+        writeln!(f, "{:<8}  {:<name_width$}  {:<issue_width$}  Detail", "Id", "Name", "Issue")?;
+        for finding in &self.findings {
+            writeln!(f, "{:<8}  {:<name_width$}  {:<issue_width$}  {}",
+                &finding.id[..8],
+                finding.name,
+                finding.issue.to_string(),
+                finding.detail
+            )?;
+        }
+        write!(f, "{} problems found in {} passwords", self.findings.len(), self.entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password(name: &str, username: &str, password: &str) -> Password {
+        Password::new(name.to_string(), username.to_string(), password.to_string())
+    }
+
+    fn issues(report: &Report, name: &str) -> Vec<Issue> {
+        report.findings.iter().filter(|finding| finding.name == name).map(|finding| finding.issue).collect()
+    }
+
+    #[test]
+    fn audit_issues() {
+        let strong = "k}`ZMz[T^7(_dq]E=_ge";
+        let passwords = vec![
+            password("strong", "me", "7rQ%vX!b2mK#p9Lz@eW4"),
+            password("first", "me", strong),
+            password("second", "me", strong),
+            password("empty", "me", ""),
+            password("weak", "me", "password"),
+            password("github", "octocat", "Github-xK9#mQ2$vL7!"),
+            password("site", "octocat", "octocat-xK9#mQ2$vL7!"),
+        ];
+        let report = audit(&passwords, Utc::now(), Duration::days(365), None).unwrap();
+
+        assert_eq!(report.entries, 7);
+        assert!(issues(&report, "strong").is_empty());
+        assert_eq!(issues(&report, "first"), vec![Issue::Reused]);
+        assert_eq!(issues(&report, "second"), vec![Issue::Reused]);
+        assert_eq!(issues(&report, "empty"), vec![Issue::Empty]);
+        assert_eq!(issues(&report, "weak"), vec![Issue::Weak]);
+        assert_eq!(issues(&report, "github"), vec![Issue::ContainsName]);
+        assert_eq!(issues(&report, "site"), vec![Issue::ContainsUsername]);
+    }
+
+    #[test]
+    fn audit_stale() {
+        let mut old = password("old", "me", "7rQ%vX!b2mK#p9Lz@eW4");
+        old.password_changed = Some("2020-01-01T00:00:00Z".to_string());
+        let mut unknown = password("unknown", "me", "k}`ZMz[T^7(_dq]E=_ge");
+        unknown.password_changed = None;
+
+        let now = DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let report = audit(&[old, unknown], now, Duration::days(180), None).unwrap();
+        assert_eq!(issues(&report, "old"), vec![Issue::Stale]);
+        assert_eq!(report.findings[0].detail, "changed 366 days ago");
+        assert!(issues(&report, "unknown").is_empty());
+    }
+
+    #[test]
+    fn audit_breached() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pwned.txt");
+        // The SHA-1 hash of "7rQ%vX!b2mK#p9Lz@eW4"
+        std::fs::write(&path, "56EFE91D1891D5158DB722C0A64F71E27E0AEF51:3\n").unwrap();
+        let mut breaches = BreachList::open(&path).unwrap();
+
+        let passwords = [password("breached", "me", "7rQ%vX!b2mK#p9Lz@eW4"), password("safe", "me", "k}`ZMz[T^7(_dq]E=_ge")];
+        let report = audit(&passwords, Utc::now(), Duration::days(365), Some(&mut breaches)).unwrap();
+        assert_eq!(issues(&report, "breached"), vec![Issue::Breached]);
+        assert_eq!(report.findings[0].detail, "seen 3 times in data breaches");
+        assert!(issues(&report, "safe").is_empty());
+
+        // Only the breached password's range is in the directory
+        let range_dir = dir.path().join("ranges");
+        std::fs::create_dir(&range_dir).unwrap();
+        std::fs::write(range_dir.join("56EFE"), "91D1891D5158DB722C0A64F71E27E0AEF51:3\n").unwrap();
+        let mut breaches = BreachList::open(&range_dir).unwrap();
+        let report = audit(&passwords, Utc::now(), Duration::days(365), Some(&mut breaches)).unwrap();
+        assert_eq!(issues(&report, "breached"), vec![Issue::Breached]);
+        assert_eq!(issues(&report, "safe"), vec![Issue::NotChecked]);
+    }
+
+    #[test]
+    fn report_json() {
+        let report = audit(&[password("empty", "me", "")], Utc::now(), Duration::days(365), None).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["entries"], 1);
+        assert_eq!(json["findings"][0]["issue"], "empty");
+        assert_eq!(json["findings"][0]["name"], "empty");
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fs, io, path::Path, time::SystemTime};
+
+use crate::atomic;
+
+pub const DEFAULT_KEEP: u32 = 5;
+
+pub struct BackupInfo {
+    pub number: u32,
+    pub path: String,
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+/// Backups sit next to the database, `.bak.1` being the most recent
+pub fn backup_path(file_path: &str, number: u32) -> String {
+    format!("{file_path}.bak.{number}")
+}
+
+/// Copy the database to `.bak.1` before it is overwritten, shifting older backups up by one
+/// and deleting any beyond the `keep` most recent
+pub fn rotate(file_path: &str, keep: u32) -> io::Result<()> {
+    prune(file_path, keep)?;
+    if keep == 0 || !Path::new(file_path).exists() {
+        return Ok(());
+    }
+
+    for number in (1..keep).rev() {
+        let path = backup_path(file_path, number);
+        if Path::new(&path).exists() {
+            fs::rename(&path, backup_path(file_path, number + 1))?;
+        }
+    }
+
+    atomic::write(backup_path(file_path, 1), &fs::read(file_path)?)
+}
+
+// Remove backups numbered `keep` or above, the next rotation moves backup `keep` out of range
+fn prune(file_path: &str, keep: u32) -> io::Result<()> {
+    let mut number = keep.max(1);
+    loop {
+        let path = backup_path(file_path, number);
+        if Path::new(&path).exists() {
+            fs::remove_file(&path)?;
+        } else if number > keep {
+            return Ok(());
+        }
+        number += 1;
+    }
+}
+
+pub fn list(file_path: &str) -> io::Result<Vec<BackupInfo>> {
+    let mut backups = vec![];
+    for number in 1.. {
+        let path = backup_path(file_path, number);
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        };
+        backups.push(BackupInfo {
+            number,
+            path,
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        });
+    }
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(file_path: &str) -> Vec<String> {
+        list(file_path)
+            .unwrap()
+            .iter()
+            .map(|backup| fs::read_to_string(&backup.path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rotate_keeps_most_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+
+        for version in 1..=5 {
+            fs::write(&file_path, format!("version {version}")).unwrap();
+            rotate(&file_path, 3).unwrap();
+        }
+        assert_eq!(contents(&file_path), vec!["version 5", "version 4", "version 3"]);
+    }
+
+    #[test]
+    fn rotate_without_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        rotate(&file_path, 3).unwrap();
+        assert!(list(&file_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rotate_prunes_when_keep_is_lowered() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+
+        for version in 1..=4 {
+            fs::write(&file_path, format!("version {version}")).unwrap();
+            rotate(&file_path, 5).unwrap();
+        }
+        fs::write(&file_path, "version 5").unwrap();
+        rotate(&file_path, 2).unwrap();
+        assert_eq!(contents(&file_path), vec!["version 5", "version 4"]);
+
+        rotate(&file_path, 0).unwrap();
+        assert!(list(&file_path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+// Starts a binary index made by `build_index`, followed by `RECORD_LEN` byte records
+const INDEX_MAGIC: &[u8; 8] = b"OXPWHIBP";
+// A SHA-1 hash and a big endian u32 count
+const RECORD_LEN: u64 = 20 + 4;
+// Range files are named after the first five hex digits of the hashes they hold
+const RANGE_PREFIX_LEN: usize = 5;
+
+/// A local copy of the Have I Been Pwned Pwned Passwords list, searched without any network
+/// access. It can be the text download ordered by hash with one `HASH:COUNT` line per
+/// password, a binary index of it made by `build_index`, or a directory of k-anonymity
+/// range files, each holding the `SUFFIX:COUNT` lines for one five digit prefix.
+pub enum BreachList {
+    SortedText(File),
+    Index(File),
+    Ranges(PathBuf),
+}
+
+impl BreachList {
+    pub fn open(path: &Path) -> Result<BreachList, BreachError> {
+        if path.is_dir() {
+            return Ok(BreachList::Ranges(path.to_path_buf()));
+        }
+
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; INDEX_MAGIC.len()];
+        let is_index = file.read_exact(&mut magic).is_ok() && &magic == INDEX_MAGIC;
+        if is_index {
+            if !(file.metadata()?.len() - INDEX_MAGIC.len() as u64).is_multiple_of(RECORD_LEN) {
+                return Err(BreachError::Malformed(path.display().to_string()));
+            }
+            Ok(BreachList::Index(file))
+        } else {
+            Ok(BreachList::SortedText(file))
+        }
+    }
+
+    /// How many times the password has been seen in breaches, 0 if it hasn't
+    pub fn count(&mut self, password: &str) -> Result<u64, BreachError> {
+        let hash = Sha1::digest(password.as_bytes());
+        match self {
+            BreachList::SortedText(file) => search_text(file, &hex_encode(&hash)),
+            BreachList::Index(file) => search_index(file, &hash),
+            BreachList::Ranges(dir) => search_range(dir, &hex_encode(&hash)),
+        }
+    }
+}
+
+// Hashes are written in uppercase hex in the lists
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Split a `HASH:COUNT` line, ignoring the case of the hash and any trailing `\r`
+fn parse_line(line: &str) -> Option<(String, u64)> {
+    let (hash, count) = line.trim_end().split_once(':')?;
+    Some((hash.to_ascii_uppercase(), count.parse().ok()?))
+}
+
+// The first line starting at or after `offset` and where it starts, lines are found by
+// backing up a byte and skipping to the end of whatever line that is in
+fn line_at(file: &mut File, offset: u64) -> io::Result<Option<(u64, String)>> {
+    let start = offset.saturating_sub(1);
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(file);
+
+    let mut skipped = vec![];
+    let line_start = if offset == 0 { 0 } else { start + reader.read_until(b'\n', &mut skipped)? as u64 };
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some((line_start, line)))
+}
+
+// Binary search by byte offset, narrowing the range to the part of the file that could hold
+// the line starting with `hash`
+fn search_text(file: &mut File, hash: &str) -> Result<u64, BreachError> {
+    let mut low = 0;
+    let mut high = file.metadata()?.len();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let Some((line_start, line)) = line_at(file, middle)? else {
+            high = middle;
+            continue;
+        };
+        let Some((line_hash, count)) = parse_line(&line) else {
+            return Err(BreachError::Malformed(line.trim_end().to_string()));
+        };
+        match line_hash.as_str().cmp(hash) {
+            Ordering::Equal => return Ok(count),
+            Ordering::Less => low = line_start + 1,
+            // No line starts between `middle` and this one, so an earlier line would start
+            // before `middle`
+            Ordering::Greater => high = middle,
+        }
+    }
+    Ok(0)
+}
+
+fn search_index(file: &mut File, hash: &[u8]) -> Result<u64, BreachError> {
+    let records = (file.metadata()?.len() - INDEX_MAGIC.len() as u64) / RECORD_LEN;
+    let (mut low, mut high) = (0, records);
+    let mut record = [0u8; RECORD_LEN as usize];
+    while low < high {
+        let middle = low + (high - low) / 2;
+        file.seek(SeekFrom::Start(INDEX_MAGIC.len() as u64 + middle * RECORD_LEN))?;
+        file.read_exact(&mut record)?;
+        match record[..20].cmp(hash) {
+            Ordering::Equal => return Ok(u32::from_be_bytes([record[20], record[21], record[22], record[23]]).into()),
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+        }
+    }
+    Ok(0)
+}
+
+fn search_range(dir: &Path, hash: &str) -> Result<u64, BreachError> {
+    let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LEN);
+    let contents = match fs::read_to_string(dir.join(prefix)).or_else(|_| fs::read_to_string(dir.join(format!("{prefix}.txt")))) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(BreachError::MissingRange(prefix.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Range files are small, but they are sorted so there is no need to read every line
+    let lines: Vec<&str> = contents.lines().filter(|line| !line.is_empty()).collect();
+    let found = lines.binary_search_by(|line| {
+        let line_suffix = line.split(':').next().unwrap_or_default();
+        line_suffix.to_ascii_uppercase().as_str().cmp(suffix)
+    });
+    match found {
+        Ok(i) => parse_line(lines[i]).map(|(_, count)| count).ok_or_else(|| BreachError::Malformed(lines[i].to_string())),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Convert the sorted text list into a binary index, a quarter of the size with fixed
+/// length records that are quicker to search. Returns the number of hashes written.
+pub fn build_index(text_path: &Path, index_path: &Path) -> Result<u64, BreachError> {
+    let reader = BufReader::new(File::open(text_path)?);
+    let mut writer = BufWriter::new(File::create(index_path)?);
+    writer.write_all(INDEX_MAGIC)?;
+
+    let mut records = 0;
+    let mut previous = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((hash, count)) = parse_line(&line) else {
+            return Err(BreachError::Malformed(line));
+        };
+        let hash = match hex_decode(&hash) {
+            Some(hash) if hash.len() == 20 && hash > previous => hash,
+            _ => return Err(BreachError::Malformed(line)),
+        };
+        writer.write_all(&hash)?;
+        writer.write_all(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes())?;
+        previous = hash;
+        records += 1;
+    }
+
+    writer.flush()?;
+    Ok(records)
+}
+
+#[derive(Error, Debug)]
+pub enum BreachError {
+    #[error("failed to read the breached password list: {0}")]
+    Io(#[from] io::Error),
+    #[error("the breached password list is not sorted `HASH:COUNT` lines, found \"{0}\"")]
+    Malformed(String),
+    #[error("there is no range file for the prefix {0}")]
+    MissingRange(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SHA-1 hashes of "password", "123456" and "letmein"
+    const PASSWORD: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+    const NUMBERS: &str = "7C4A8D09CA3762AF61E59520943DC26494F8941B";
+    const LETMEIN: &str = "B7A875FC1EA228B9061041B7CEC4BD3C52AB3CE3";
+
+    fn write_text(dir: &Path) -> PathBuf {
+        let path = dir.join("pwned.txt");
+        // Lines either side of each password, so every search has neighbours to skip past
+        let contents = format!("\
+0000000000000000000000000000000000000001:5\r
+{PASSWORD}:10434004\r
+5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9:1\r
+{NUMBERS}:37359195\r
+{LETMEIN}:2\r
+FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:7\r
+");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn assert_counts(list: &mut BreachList) {
+        assert_eq!(list.count("password").unwrap(), 10434004);
+        assert_eq!(list.count("123456").unwrap(), 37359195);
+        assert_eq!(list.count("letmein").unwrap(), 2);
+        assert_eq!(list.count("k}`ZMz[T^7(_dq]E=_ge").unwrap(), 0);
+    }
+
+    #[test]
+    fn sha1_hashes() {
+        assert_eq!(hex_encode(&Sha1::digest(b"password")), PASSWORD);
+        assert_eq!(hex_decode(PASSWORD).unwrap(), Sha1::digest(b"password").to_vec());
+        assert_eq!(hex_decode("5BA"), None);
+    }
+
+    #[test]
+    fn search_sorted_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut list = BreachList::open(&write_text(dir.path())).unwrap();
+        assert!(matches!(list, BreachList::SortedText(_)));
+        assert_counts(&mut list);
+
+        // The first and last lines can be found too
+        let BreachList::SortedText(file) = &mut list else { unreachable!() };
+        assert_eq!(search_text(file, "0000000000000000000000000000000000000001").unwrap(), 5);
+        assert_eq!(search_text(file, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap(), 7);
+        assert_eq!(search_text(file, "0000000000000000000000000000000000000000").unwrap(), 0);
+    }
+
+    #[test]
+    fn search_binary_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let index_path = dir.path().join("pwned.idx");
+        assert_eq!(build_index(&write_text(dir.path()), &index_path).unwrap(), 6);
+
+        let mut list = BreachList::open(&index_path).unwrap();
+        assert!(matches!(list, BreachList::Index(_)));
+        assert_counts(&mut list);
+    }
+
+    #[test]
+    fn build_index_rejects_unsorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let text_path = dir.path().join("pwned.txt");
+        fs::write(&text_path, format!("{NUMBERS}:1\n{PASSWORD}:1\n")).unwrap();
+        assert!(matches!(build_index(&text_path, &dir.path().join("pwned.idx")), Err(BreachError::Malformed(_))));
+    }
+
+    #[test]
+    fn search_range_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("5BAA6"), format!("0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n{}:10434004\r\n", &PASSWORD[5..])).unwrap();
+        fs::write(dir.path().join("7C4A8.txt"), format!("{}:37359195\n", &NUMBERS[5..])).unwrap();
+        fs::write(dir.path().join("B7A87"), format!("{}:2\n", &LETMEIN[5..].to_lowercase())).unwrap();
+        fs::write(dir.path().join("CF06F"), "003D68EB55068C33ACE09247EE4C639306B:3\n").unwrap();
+
+        let mut list = BreachList::open(dir.path()).unwrap();
+        assert_counts(&mut list);
+        assert!(matches!(list.count("hunter2"), Err(BreachError::MissingRange(_))));
+    }
+}
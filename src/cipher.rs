@@ -0,0 +1,92 @@
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const NONCE_LEN: usize = 24;
+
+/// The scheme used to encrypt the passwords in a database
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "xchacha20poly1305")]
+    XChaCha20Poly1305,
+}
+
+/// Encrypt with XChaCha20-Poly1305 under a fresh random nonce. The nonce is prepended to
+/// the ciphertext, and the associated data must be given again to decrypt.
+pub fn seal(key: &[u8], plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, CipherError> {
+    let aead = XChaCha20Poly1305::new_from_slice(key).map_err(|_| CipherError::InvalidKey)?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = aead
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: associated_data })
+        .map_err(|_| CipherError::EncryptionFailure)?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+pub fn open(key: &[u8], sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, CipherError> {
+    let aead = XChaCha20Poly1305::new_from_slice(key).map_err(|_| CipherError::InvalidKey)?;
+
+    if sealed.len() < NONCE_LEN {
+        return Err(CipherError::Malformed);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    aead.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data })
+        .map_err(|_| CipherError::AuthenticationFailure)
+}
+
+#[derive(Error, Debug)]
+pub enum CipherError {
+    #[error("encryption keys must be 32 bytes long")]
+    InvalidKey,
+    #[error("failed to encrypt data")]
+    EncryptionFailure,
+    #[error("ciphertext is too short to contain a nonce")]
+    Malformed,
+    #[error("ciphertext failed authentication, it is corrupt, was encrypted with a different key or has been tampered with")]
+    AuthenticationFailure,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3u8; 32];
+
+    #[test]
+    fn seal_open() {
+        let sealed = seal(&KEY, b"secret", b"context").unwrap();
+        assert_eq!(open(&KEY, &sealed, b"context").unwrap(), b"secret");
+    }
+
+    #[test]
+    fn seal_uses_fresh_nonce() {
+        assert_ne!(seal(&KEY, b"secret", b"context").unwrap(), seal(&KEY, b"secret", b"context").unwrap());
+    }
+
+    #[test]
+    fn open_rejects_wrong_associated_data() {
+        let sealed = seal(&KEY, b"secret", b"context").unwrap();
+        assert!(matches!(open(&KEY, &sealed, b"other"), Err(CipherError::AuthenticationFailure)));
+    }
+
+    #[test]
+    fn open_rejects_modified_ciphertext() {
+        let mut sealed = seal(&KEY, b"secret", b"context").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(matches!(open(&KEY, &sealed, b"context"), Err(CipherError::AuthenticationFailure)));
+    }
+
+    #[test]
+    fn rejects_short_key() {
+        assert!(matches!(seal(b"short", b"secret", b"context"), Err(CipherError::InvalidKey)));
+    }
+}
@@ -1,26 +1,501 @@
-use std::{collections::HashMap, usize};
+use std::{collections::HashMap, iter::Peekable, time::Duration};
 use thiserror::Error;
 
+use crate::{
+    audit,
+    expiry,
+    field::{self, FieldKind},
+    folder,
+    format::{Header, Layout},
+    generator::Policy,
+    kdf::KdfParams,
+    kind::EntryKind,
+    lock,
+    otp,
+    passphrase::{PassphraseOptions, Wordlist},
+    password::{CustomField, Password},
+    secret::SecretSource,
+    strength,
+    trash,
+};
+
+/// Passwords are never parsed from the arguments, `pass` and the new master password are
+/// filled in by `run` from the configured `SecretSource`
 pub enum Command {
-    List,
-    New {name: Option<String>, user: Option<String>, pass: Option<String>},
-    Edit {item: Option<usize>, name: Option<String>, user: Option<String>, pass: Option<String>},
-    Delete(Option<usize>),
-    Get(Option<usize>),
+    Create(KdfOptions, LayoutOptions),
+    Generate(GeneratorOptions),
+    Passphrase(PassphraseOptions),
+    List(ListOptions),
+    New {name: Option<String>, kind: EntryKind, user: Option<String>, pass: Option<String>, generate: Option<GeneratorOptions>, fields: FieldOptions},
+    // `ask_pass` is set by `-p`, asking for a new password
+    Edit {item: String, name: Option<String>, user: Option<String>, pass: Option<String>, ask_pass: bool, generate: Option<GeneratorOptions>, fields: FieldOptions},
+    // Moves the password to the trash, `yes` skips asking first
+    Delete {item: String, yes: bool},
+    Get {item: String, show_hidden: bool},
+    History(String),
+    // Restore the nth previous password, counting from 1 for the most recent
+    Restore {item: String, number: u32},
+    Otp(String),
+    // Move to a folder, `None` is the top level
+    Move {item: String, folder: Option<String>},
+    Tag {item: String, add: Vec<String>, remove: Vec<String>},
+    // `name` defaults to the file's name
+    Attach {item: String, file: String, name: Option<String>},
+    Attachments(String),
+    // Written to `output`, a file named after the attachment or `-` for stdout
+    Extract {item: String, name: String, output: Option<String>},
+    Detach {item: String, name: String},
+    // Passwords due to be changed within this long, see `expiry`
+    Due {within: chrono::Duration},
     ChangeMaster(Option<String>),
+    UpdateKdf(KdfOptions),
+    SetLayout(LayoutOptions),
+    ListBackups,
+    RestoreBackup(u32),
+    Audit {max_age_days: u32, json: bool, breaches: Option<String>},
+    BuildBreachIndex {text_path: String, index_path: String},
+    KeepBackups(u32),
+    ListTrash,
+    RestoreTrash(String),
+    EmptyTrash {yes: bool},
+    KeepTrash(u32),
     Help,
-    None
+}
+
+impl Command {
+    // Read-only commands can share the database with each other, everything else needs
+    // it to themselves. `get` records when a password was last used, so it isn't read-only.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, Command::List(_) | Command::History(_) | Command::Due { .. } | Command::Attachments(_) | Command::Extract { .. } | Command::ListBackups | Command::ListTrash | Command::Audit { .. })
+    }
+}
+
+/// Key derivation settings given on the command line, anything left out falls back to
+/// the database's current setting (or the default for a new database)
+#[derive(Default)]
+pub struct KdfOptions {
+    pub memory_kib: Option<u32>,
+    pub iterations: Option<u32>,
+    pub parallelism: Option<u32>,
+}
+
+impl KdfOptions {
+    pub fn apply(&self, base: KdfParams) -> KdfParams {
+        KdfParams {
+            memory_kib: self.memory_kib.unwrap_or(base.memory_kib),
+            iterations: self.iterations.unwrap_or(base.iterations),
+            parallelism: self.parallelism.unwrap_or(base.parallelism),
+        }
+    }
+
+    // Returns false if `flag` isn't a key derivation option
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, ConfigError> {
+        let value = match flag {
+            "-m" | "--memory" => &mut self.memory_kib,
+            "-t" | "--iterations" => &mut self.iterations,
+            "-p" | "--parallelism" => &mut self.parallelism,
+            _ => return Ok(false),
+        };
+        *value = Some(parse_number(flag, args)?);
+        Ok(true)
+    }
+}
+
+/// How the database body is laid out on disk, anything left out keeps the database's
+/// current setting (or the default for a new database)
+#[derive(Default)]
+pub struct LayoutOptions {
+    pub layout: Option<Layout>,
+    pub padding: Option<u32>,
+}
+
+impl LayoutOptions {
+    pub fn apply(&self, header: &mut Header) {
+        if let Some(layout) = self.layout { header.layout = layout; }
+        if let Some(padding) = self.padding { header.padding = padding; }
+    }
+
+    // Returns false if `flag` isn't a layout option
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, ConfigError> {
+        match flag {
+            "--sealed" => self.layout = Some(Layout::Sealed),
+            "--entries" => self.layout = Some(Layout::Entries),
+            "--pad" => self.padding = Some(parse_number(flag, args)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    Created,
+    Modified,
+    Changed,
+    Accessed,
+}
+
+/// Which passwords `list` shows and in what order, they are listed in the order they were
+/// added unless a sort is given
+#[derive(Default)]
+pub struct ListOptions {
+    pub sort: Option<SortKey>,
+    // Only list passwords that were last changed longer ago than this
+    pub older_than: Option<chrono::Duration>,
+    // Only list passwords in this folder or its subfolders, `Some(None)` lists the ones that
+    // aren't in a folder
+    pub folder: Option<Option<String>>,
+    // Only list passwords with all of these tags
+    pub tags: Vec<String>,
+    // Only list entries of this kind
+    pub kind: Option<EntryKind>,
+    // Group the passwords under their folders
+    pub tree: bool,
+}
+
+impl ListOptions {
+    // Returns false if `flag` isn't a list option
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, ConfigError> {
+        match flag {
+            "--sort" => self.sort = Some(match args.next().as_deref() {
+                Some("name") => SortKey::Name,
+                Some("created") => SortKey::Created,
+                Some("modified") => SortKey::Modified,
+                Some("changed") => SortKey::Changed,
+                Some("used") => SortKey::Accessed,
+                _ => return Err(ConfigError::CommandError(format!("`{flag}` needs one of name, created, modified, changed or used"))),
+            }),
+            "--older-than" => self.older_than = Some(parse_age(flag, args)?),
+            "--folder" => match args.next() {
+                Some(folder) => self.folder = Some(folder::normalize_folder(&folder)),
+                None => return Err(ConfigError::CommandError(format!("`{flag}` needs the folder to list"))),
+            },
+            "--tag" => match args.next() {
+                Some(tag) => self.tags.push(folder::normalize_tag(&tag).map_err(|e| ConfigError::CommandError(e.to_string()))?),
+                None => return Err(ConfigError::CommandError(format!("`{flag}` needs the tag to list"))),
+            },
+            "--kind" => self.kind = Some(parse_kind(flag, args)?),
+            "--tree" => self.tree = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// How to generate a password, for the `generate` command or `--generate` on `new`/`edit`
+#[derive(Default)]
+pub struct GeneratorOptions {
+    pub policy: Policy,
+    // Print a password generated by `new`/`edit`, `generate` always prints it
+    pub show: bool,
+}
+
+impl GeneratorOptions {
+    // Returns false if `flag` isn't a generator option
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, ConfigError> {
+        match flag {
+            "-l" | "--length" => self.policy.length = parse_number(flag, args)? as usize,
+            "--max-length" => self.policy.max_length = Some(parse_number(flag, args)? as usize),
+            "--no-lower" => self.policy.lower = false,
+            "--no-upper" => self.policy.upper = false,
+            "--no-digits" => self.policy.digits = false,
+            "--no-symbols" => self.policy.symbols = false,
+            "--symbols" => match args.next() {
+                Some(symbols) => self.policy.allowed_symbols = Some(symbols),
+                None => return Err(ConfigError::CommandError(format!("`{flag}` needs the symbols to allow"))),
+            },
+            "--no-ambiguous" => self.policy.avoid_ambiguous = true,
+            "--show" => self.show = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Notes, URLs, custom fields and details to set on `new`/`edit`. The values of hidden fields
+/// and asked for details are secrets, so they are filled in by `run` from the configured
+/// `SecretSource`.
+#[derive(Default)]
+pub struct FieldOptions {
+    // An empty note removes the notes
+    pub notes: Option<String>,
+    pub add_urls: Vec<String>,
+    pub remove_urls: Vec<String>,
+    // Fields with the name of an existing field replace it
+    pub set_fields: Vec<CustomField>,
+    pub remove_fields: Vec<String>,
+    // Set by `--otp`, asking for an otpauth URI or base32 secret which `run` puts in `otp`
+    pub ask_otp: bool,
+    pub otp: Option<String>,
+    pub remove_otp: bool,
+    // Details given as `key=value`, which can't be secret details
+    pub set_details: Vec<(String, String)>,
+    // Details read from a file by `--detail-file`, or asked for by `run` when `None`
+    pub secret_details: Vec<(String, Option<String>)>,
+    pub remove_details: Vec<String>,
+    // `Some(None)` removes the expiry date or rotation interval
+    pub expires: Option<Option<String>>,
+    pub rotate_days: Option<Option<u32>>,
+}
+
+impl FieldOptions {
+    // Returns false if `flag` isn't a field option, the remove options are only for `edit`
+    fn parse_flag<I: Iterator<Item = String>>(&mut self, flag: &str, args: &mut Peekable<I>, editing: bool) -> Result<bool, ConfigError> {
+        let mut value = || args.next().ok_or_else(|| ConfigError::CommandError(format!("`{flag}` needs a value")));
+        match flag {
+            "--notes" => self.notes = Some(value()?),
+            "--url" => self.add_urls.push(field::normalize_url(value()?).map_err(field_error)?),
+            "--field" => {
+                // `[<kind>:]<name>=<value>`
+                let field = value()?;
+                let (kind, field) = match field.split_once(':').map(|(kind, rest)| (FieldKind::parse(kind), rest)) {
+                    Some((Some(kind), rest)) => (kind, rest),
+                    _ => (FieldKind::Text, field.as_str()),
+                };
+                let Some((name, value)) = field.split_once('=') else {
+                    return Err(ConfigError::CommandError(format!("`{flag}` needs a name and value, like `{flag} email:recovery=me@example.com`")));
+                };
+                if kind == FieldKind::Hidden {
+                    return Err(secret_argument());
+                }
+                let value = field::normalize(kind, value.to_string()).map_err(field_error)?;
+                self.set_fields.push(CustomField { name: name.to_string(), kind, value });
+            },
+            "--hidden" => self.set_fields.push(CustomField { name: value()?, kind: FieldKind::Hidden, value: String::new() }),
+            "--remove-url" if editing => self.remove_urls.push(field::normalize_url(value()?).map_err(field_error)?),
+            "--remove-field" if editing => self.remove_fields.push(value()?),
+            "--otp" => {
+                // Like passwords, the seed is a secret. Anything else after it is left for the
+                // other arguments, like a username.
+                if args.peek().is_some_and(|next| otp::looks_like_secret(next)) {
+                    return Err(secret_argument());
+                }
+                self.ask_otp = true;
+            },
+            "--remove-otp" if editing => self.remove_otp = true,
+            "--detail" => {
+                // `<key>=<value>`, or only the key to be asked for a secret value
+                let detail = value()?;
+                match detail.split_once('=') {
+                    Some((key, value)) => self.set_details.push((key.to_string(), value.to_string())),
+                    None => self.secret_details.push((detail, None)),
+                }
+            },
+            "--detail-file" => {
+                let detail = value()?;
+                let Some((key, path)) = detail.split_once('=') else {
+                    return Err(ConfigError::CommandError(format!("`{flag}` needs a key and file, like `{flag} private_key=~/.ssh/id_ed25519`")));
+                };
+                let value = std::fs::read_to_string(path)
+                    .map_err(|e| ConfigError::CommandError(format!("Failed to read the {key} from {path}: {e}")))?;
+                self.secret_details.push((key.to_string(), Some(value)));
+            },
+            "--remove-detail" if editing => self.remove_details.push(value()?),
+            "--expires" => self.expires = Some(match value()?.as_str() {
+                "never" => None,
+                date => match expiry::parse_date(date) {
+                    Some(date) => Some(date.format("%Y-%m-%d").to_string()),
+                    None => return Err(ConfigError::CommandError(format!("`{flag}` needs a date like 2030-12-31, or never"))),
+                },
+            }),
+            "--rotate" => self.rotate_days = Some(match value()?.as_str() {
+                "never" => None,
+                age => match parse_age(flag, &mut std::iter::once(age.to_string()))?.num_days() {
+                    0 => return Err(ConfigError::CommandError(format!("`{flag}` needs at least a day, like 90d"))),
+                    days if days > expiry::MAX_ROTATE_DAYS.into() => {
+                        return Err(ConfigError::CommandError(format!("`{flag}` can be at most {} days", expiry::MAX_ROTATE_DAYS)))
+                    },
+                    days => Some(days as u32),
+                },
+            }),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Returns whether anything was changed
+    pub fn apply(self, password: &mut Password) -> Result<bool, String> {
+        let mut changed = false;
+        for url in self.remove_urls {
+            let Some(position) = password.urls.iter().position(|saved| *saved == url) else {
+                return Err(format!("{} has no URL {url}", password.name));
+            };
+            password.urls.remove(position);
+            changed = true;
+        }
+        for name in self.remove_fields {
+            let Some(position) = password.fields.iter().position(|field| field.name == name) else {
+                return Err(format!("{} has no field named \"{name}\"", password.name));
+            };
+            password.fields.remove(position);
+            changed = true;
+        }
+        if self.remove_otp {
+            if password.otp.is_none() {
+                return Err(format!("{} has no one-time password", password.name));
+            }
+            password.otp = None;
+            changed = true;
+        }
+        for key in self.remove_details {
+            if let Some(detail) = password.kind.detail(&key).filter(|detail| detail.required) {
+                return Err(format!("A {} needs a {}, it can be changed but not removed", password.kind, detail.label.to_lowercase()));
+            }
+            if password.details.remove(&key).is_none() {
+                return Err(format!("{} has no {key}", password.name));
+            }
+            changed = true;
+        }
+        for (key, value) in self.set_details {
+            if let Some(detail) = password.kind.detail(&key).filter(|detail| detail.secret) {
+                return Err(format!("The {} is a secret, use `--detail {key}` to be asked for it or `--detail-file {key}=<file>`", detail.label.to_lowercase()));
+            }
+            password.details.insert(key.clone(), password.kind.check(&key, value)?);
+            changed = true;
+        }
+        for (key, value) in self.secret_details {
+            password.details.insert(key.clone(), password.kind.check(&key, value.unwrap_or_default())?);
+            changed = true;
+        }
+        if let Some(otp) = self.otp {
+            password.otp = Some(otp);
+            changed = true;
+        }
+        if let Some(expires) = self.expires {
+            password.expires = expires;
+            changed = true;
+        }
+        if let Some(rotate_days) = self.rotate_days {
+            password.rotate_days = rotate_days.map(|days| days.to_string());
+            changed = true;
+        }
+        if let Some(notes) = self.notes {
+            password.notes = Some(notes).filter(|notes| !notes.is_empty());
+            changed = true;
+        }
+        for url in self.add_urls {
+            if !password.urls.contains(&url) {
+                password.urls.push(url);
+                changed = true;
+            }
+        }
+        for field in self.set_fields {
+            match password.fields.iter_mut().find(|saved| saved.name == field.name) {
+                Some(saved) => *saved = field,
+                None => password.fields.push(field),
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+fn parse_kind(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<EntryKind, ConfigError> {
+    args.next().as_deref().and_then(EntryKind::parse).ok_or_else(|| ConfigError::CommandError(
+        format!("`{flag}` needs one of login, note, card, identity, ssh-key, api-token or wifi"),
+    ))
+}
+
+fn field_error(error: field::FieldError) -> ConfigError {
+    ConfigError::CommandError(error.to_string())
+}
+
+// Returns false if `flag` isn't a passphrase option
+fn parse_passphrase_flag(options: &mut PassphraseOptions, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, ConfigError> {
+    match flag {
+        "-w" | "--words" => options.words = parse_number(flag, args)? as usize,
+        "--short" => options.wordlist = Wordlist::Short,
+        "--separator" => match args.next() {
+            Some(separator) => options.separator = separator,
+            None => return Err(ConfigError::CommandError(format!("`{flag}` needs the separator to use"))),
+        },
+        "--capitalize" => options.capitalize = true,
+        "--digit" => options.digit = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_number(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<u32, ConfigError> {
+    match args.next().map(|n| n.parse::<u32>()) {
+        Some(Ok(n)) => Ok(n),
+        _ => Err(ConfigError::CommandError(format!("`{flag}` needs a whole number value"))),
+    }
+}
+
+// Ages are a whole number with a unit of hours, days, weeks, months or years, like `180d`.
+// Months are 30 days and years are 365.
+fn parse_age(flag: &str, args: &mut impl Iterator<Item = String>) -> Result<chrono::Duration, ConfigError> {
+    let invalid = || ConfigError::CommandError(format!("`{flag}` needs an age like 48h, 180d, 12w, 6m or 1y"));
+    let age = args.next().ok_or_else(invalid)?;
+    let (number, unit) = age.split_at(age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len()));
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+    let hours = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        "m" => 24 * 30,
+        "y" => 24 * 365,
+        _ => return Err(invalid()),
+    };
+    number.checked_mul(hours).and_then(chrono::Duration::try_hours).ok_or_else(invalid)
+}
+
+fn unknown_option(flag: &str) -> ConfigError {
+    ConfigError::CommandError(format!("Unknown option `{flag}`"))
+}
+
+fn secret_argument() -> ConfigError {
+    ConfigError::CommandError("Passwords can't be given as arguments, where other users and shell history can see them. \
+Enter them when asked, or pass --password-stdin or --password-fd <fd> to read them one per line".to_string())
 }
 
 pub struct Config {
     pub database_name: String,
     pub command: Command,
+    pub lock_timeout: Duration,
+    pub secret_source: SecretSource,
+    // New master passwords scoring lower than this are refused, see `strength::estimate`
+    pub min_master_score: u8,
 }
 
 impl Config {
     pub fn build(
-        mut args: impl Iterator<Item = String>,
+        mut all_args: impl Iterator<Item = String>,
     ) -> Result<Config, ConfigError> {
+        // Global options can go anywhere, so take them out before reading the positional args
+        let mut lock_timeout = lock::DEFAULT_TIMEOUT;
+        let mut secret_source = SecretSource::Prompt;
+        let mut min_master_score = strength::GOOD_SCORE;
+        let mut filtered_args = vec![];
+        while let Some(arg) = all_args.next() {
+            let source = match arg.as_str() {
+                "--lock-timeout" => {
+                    lock_timeout = Duration::from_secs(parse_number(&arg, &mut all_args)?.into());
+                    continue;
+                },
+                "--min-score" => {
+                    min_master_score = match parse_number(&arg, &mut all_args)? {
+                        score if score <= strength::MAX_SCORE.into() => score as u8,
+                        _ => return Err(ConfigError::CommandError(format!("`{arg}` needs a score from 0 to {}", strength::MAX_SCORE))),
+                    };
+                    continue;
+                },
+                "--password-stdin" => SecretSource::Stdin,
+                "--password-fd" => SecretSource::Fd(parse_number(&arg, &mut all_args)?),
+                _ => {
+                    filtered_args.push(arg);
+                    continue;
+                },
+            };
+            if secret_source != SecretSource::Prompt {
+                return Err(ConfigError::CommandError("Only one of --password-stdin and --password-fd can be given".to_string()));
+            }
+            secret_source = source;
+        }
+        let mut args = filtered_args.into_iter();
+
         args.next();
 
         // For operations on a database file, the next arg will be the database name,
@@ -29,13 +504,50 @@ impl Config {
             Some("new") => {
                 match args.next() {
                     Some(name) => {
-                        return Ok(Config { database_name: name, command: Command::None });
+                        let mut kdf_options = KdfOptions::default();
+                        let mut layout_options = LayoutOptions::default();
+                        while let Some(arg) = args.next() {
+                            if !kdf_options.parse_flag(&arg, &mut args)? && !layout_options.parse_flag(&arg, &mut args)? {
+                                return Err(unknown_option(&arg));
+                            }
+                        }
+                        return Ok(Config { database_name: name, command: Command::Create(kdf_options, layout_options), lock_timeout, secret_source, min_master_score });
                     },
                     None => return Err(ConfigError::CommandError("No database name was entered for the `new` command".to_string())),
                 }
             },
+            Some("generate") => {
+                let mut generator_options = GeneratorOptions::default();
+                while let Some(arg) = args.next() {
+                    if !generator_options.parse_flag(&arg, &mut args)? {
+                        return Err(unknown_option(&arg));
+                    }
+                }
+                return Ok(Config { database_name: String::from(""), command: Command::Generate(generator_options), lock_timeout, secret_source, min_master_score });
+            },
+            Some("passphrase") => {
+                let mut passphrase_options = PassphraseOptions::default();
+                while let Some(arg) = args.next() {
+                    if !parse_passphrase_flag(&mut passphrase_options, &arg, &mut args)? {
+                        return Err(unknown_option(&arg));
+                    }
+                }
+                return Ok(Config { database_name: String::from(""), command: Command::Passphrase(passphrase_options), lock_timeout, secret_source, min_master_score });
+            },
+            Some("breach-index") => {
+                let (Some(text_path), Some(index_path)) = (args.next(), args.next()) else {
+                    return Err(ConfigError::CommandError("`breach-index` needs the Pwned Passwords text file and the index file to write".to_string()));
+                };
+                return Ok(Config {
+                    database_name: String::from(""),
+                    command: Command::BuildBreachIndex { text_path, index_path },
+                    lock_timeout,
+                    secret_source,
+                    min_master_score,
+                });
+            },
             Some("help") | Some("-h") | Some("--help") => {
-                return Ok(Config { database_name: String::from(""), command: Command::Help});
+                return Ok(Config { database_name: String::from(""), command: Command::Help, lock_timeout, secret_source, min_master_score });
             },
             Some(arg) => arg.to_string(),
             None => return Err(ConfigError::CommandError("No command options entered".to_string())),
@@ -44,31 +556,85 @@ impl Config {
         // Sort command inputs into a Command enum
         let command = match args.next() {
             Some(arg) => match arg.as_str() {
-                "list" => Command::List,
-                "new" => Command::New { name: args.next(), user: args.next(), pass: args.next() },
+                "list" => {
+                    let mut list_options = ListOptions::default();
+                    while let Some(arg) = args.next() {
+                        if !list_options.parse_flag(&arg, &mut args)? {
+                            return Err(unknown_option(&arg));
+                        }
+                    }
+
+                    Command::List(list_options)
+                },
+                "new" => {
+                    let Some(name) = args.next() else {
+                        return Err(ConfigError::CommandError("No name was given for the new password".to_string()));
+                    };
+                    let mut user = None;
+                    let mut kind = EntryKind::Login;
+                    let mut generator_options = GeneratorOptions::default();
+                    let mut generate = false;
+                    let mut field_options = FieldOptions::default();
+                    let mut args = args.by_ref().peekable();
+                    while let Some(arg) = args.next() {
+                        if arg == "--generate" || generator_options.parse_flag(&arg, &mut args)? {
+                            generate = true;
+                        } else if arg == "--kind" {
+                            kind = parse_kind(&arg, &mut args)?;
+                        } else if field_options.parse_flag(&arg, &mut args, false)? {
+                            continue;
+                        } else if arg.starts_with('-') {
+                            return Err(unknown_option(&arg));
+                        } else if user.is_none() {
+                            user = Some(arg);
+                        } else {
+                            return Err(secret_argument());
+                        }
+                    }
+
+                    Command::New { name: Some(name), kind, user, pass: None, generate: generate.then_some(generator_options), fields: field_options }
+                },
                 "edit" => {
-                    let item = match args.next() {
-                        Some(n) => n.parse::<usize>(),
-                        None => return Err(ConfigError::CommandError("Invalid or no password ID given".to_string())),
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
                     };
 
                     let mut edit_args: HashMap<&str, Option<String>> = HashMap::new();
+                    let mut ask_pass = false;
+                    let mut generator_options = GeneratorOptions::default();
+                    let mut generate = false;
+
+                    let mut field_options = FieldOptions::default();
 
-                    loop {
-                        let arg = args.next();
-                        if arg.is_none() {
-                            break;
+                    let mut args = args.by_ref().peekable();
+                    while let Some(arg) = args.next() {
+                        if arg == "--generate" || generator_options.parse_flag(&arg, &mut args)? {
+                            generate = true;
+                            continue;
                         }
-                        match arg.unwrap().as_str() {
+                        if field_options.parse_flag(&arg, &mut args, true)? {
+                            continue;
+                        }
+                        match arg.as_str() {
                             "-n" => edit_args.insert("-n", args.next()),
                             "-u" => edit_args.insert("-u", args.next()),
-                            "-p" => edit_args.insert("-p", args.next()),
+                            "-p" => {
+                                // `-p` used to take the new password as its value
+                                if args.peek().is_some_and(|next| !next.starts_with('-')) {
+                                    return Err(secret_argument());
+                                }
+                                ask_pass = true;
+                                None
+                            },
                             _ => None,
                         };
                     }
+                    if ask_pass && generate {
+                        return Err(ConfigError::CommandError("Only one of -p and --generate can be given".to_string()));
+                    }
 
                     Command::Edit { 
-                        item: item.ok(), 
+                        item,
                         name: match edit_args.get("-n") {
                             Some(name) => name.clone(),
                             None => None,
@@ -77,41 +643,233 @@ impl Config {
                             Some(user) => user.clone(),
                             None => None,
                         }, 
-                        pass: match edit_args.get("-p") {
-                            Some(pass) => pass.clone(),
-                            None => None,
-                        },
+                        pass: None,
+                        ask_pass,
+                        generate: generate.then_some(generator_options),
+                        fields: field_options,
                     }
                 },
                 "delete" => {
-                    let item = match args.next() {
-                        Some(n) => n.parse::<usize>(),
-                        None => return Err(ConfigError::CommandError("Invalid or no password ID given".to_string())),
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
                     };
 
-                    Command::Delete(item.ok())
+                    let mut yes = false;
+                    for arg in args.by_ref() {
+                        match arg.as_str() {
+                            "-y" | "--yes" => yes = true,
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
+
+                    Command::Delete { item, yes }
                 },
                 "get" => {
-                    let item = match args.next() {
-                        Some(n) => n.parse::<usize>(),
-                        None => return Err(ConfigError::CommandError("Invalid or no password ID given".to_string())),
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+
+                    let mut show_hidden = false;
+                    for arg in args.by_ref() {
+                        match arg.as_str() {
+                            "--show-hidden" => show_hidden = true,
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
+
+                    Command::Get { item, show_hidden }
+                },
+                "history" => {
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+
+                    Command::History(item)
+                },
+                "otp" => {
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+
+                    Command::Otp(item)
+                },
+                "mv" => {
+                    let (Some(item), Some(folder)) = (args.next(), args.next()) else {
+                        return Err(ConfigError::CommandError("`mv` needs a password id or name and the folder to move it to".to_string()));
+                    };
+
+                    Command::Move { item, folder: folder::normalize_folder(&folder) }
+                },
+                "tag" => {
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+                    let mut add = vec![];
+                    let mut remove = vec![];
+                    while let Some(arg) = args.next() {
+                        let (tags, tag) = match arg.as_str() {
+                            "-r" | "--remove" => match args.next() {
+                                Some(tag) => (&mut remove, tag),
+                                None => return Err(ConfigError::CommandError(format!("`{arg}` needs the tag to remove"))),
+                            },
+                            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+                            _ => (&mut add, arg),
+                        };
+                        tags.push(folder::normalize_tag(&tag).map_err(|e| ConfigError::CommandError(e.to_string()))?);
+                    }
+                    if add.is_empty() && remove.is_empty() {
+                        return Err(ConfigError::CommandError("No tags given to add or remove".to_string()));
+                    }
+
+                    Command::Tag { item, add, remove }
+                },
+                "attach" => {
+                    let (Some(item), Some(file)) = (args.next(), args.next()) else {
+                        return Err(ConfigError::CommandError("`attach` needs a password id or name and the file to attach".to_string()));
                     };
+                    let mut name = None;
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "--name" => match args.next() {
+                                Some(value) => name = Some(value),
+                                None => return Err(ConfigError::CommandError(format!("`{arg}` needs the name to attach the file as"))),
+                            },
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
 
-                    Command::Get(item.ok())
+                    Command::Attach { item, file, name }
+                },
+                "attachments" => {
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+
+                    Command::Attachments(item)
+                },
+                "extract" => {
+                    let (Some(item), Some(name)) = (args.next(), args.next()) else {
+                        return Err(ConfigError::CommandError("`extract` needs a password id or name and the name of the attachment".to_string()));
+                    };
+                    let mut output = None;
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "-o" | "--output" => match args.next() {
+                                Some(path) => output = Some(path),
+                                None => return Err(ConfigError::CommandError(format!("`{arg}` needs the file to write to"))),
+                            },
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
+
+                    Command::Extract { item, name, output }
+                },
+                "detach" => {
+                    let (Some(item), Some(name)) = (args.next(), args.next()) else {
+                        return Err(ConfigError::CommandError("`detach` needs a password id or name and the name of the attachment".to_string()));
+                    };
+
+                    Command::Detach { item, name }
+                },
+                "due" => {
+                    let mut within = chrono::Duration::days(expiry::DEFAULT_WITHIN_DAYS.into());
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "--within" => within = parse_age(&arg, &mut args)?,
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
+
+                    Command::Due { within }
+                },
+                "restore" => {
+                    let Some(item) = args.next() else {
+                        return Err(ConfigError::CommandError("No password id or name given".to_string()));
+                    };
+
+                    Command::Restore { item, number: parse_number("restore", &mut args)? }
                 },
                 "updatepass" => {
-                    let new_pass = args.next();
+                    if args.next().is_some() {
+                        return Err(secret_argument());
+                    }
 
-                    Command::ChangeMaster(new_pass)
-                }
+                    Command::ChangeMaster(None)
+                },
+                "updatekdf" => {
+                    let mut kdf_options = KdfOptions::default();
+                    while let Some(arg) = args.next() {
+                        if !kdf_options.parse_flag(&arg, &mut args)? {
+                            return Err(unknown_option(&arg));
+                        }
+                    }
+
+                    Command::UpdateKdf(kdf_options)
+                },
+                "layout" => {
+                    let mut layout_options = LayoutOptions::default();
+                    while let Some(arg) = args.next() {
+                        if !layout_options.parse_flag(&arg, &mut args)? {
+                            return Err(unknown_option(&arg));
+                        }
+                    }
+
+                    Command::SetLayout(layout_options)
+                },
+                "audit" => {
+                    let mut max_age_days = audit::DEFAULT_MAX_AGE_DAYS;
+                    let mut json = false;
+                    let mut breaches = None;
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "--json" => json = true,
+                            "--max-age" => max_age_days = parse_number(&arg, &mut args)?,
+                            "--breaches" => match args.next() {
+                                Some(path) => breaches = Some(path),
+                                None => return Err(ConfigError::CommandError(format!("`{arg}` needs the path of a Pwned Passwords list"))),
+                            },
+                            _ => return Err(unknown_option(&arg)),
+                        }
+                    }
+
+                    Command::Audit { max_age_days, json, breaches }
+                },
+                "backups" => match args.next().as_deref() {
+                    None | Some("list") => Command::ListBackups,
+                    Some(flag @ "restore") => Command::RestoreBackup(parse_number(flag, &mut args)?),
+                    Some(flag @ "keep") => Command::KeepBackups(parse_number(flag, &mut args)?),
+                    Some(arg) => return Err(ConfigError::CommandError(format!("Unknown backups command `{arg}`"))),
+                },
+                "trash" => match args.next().as_deref() {
+                    None | Some("list") => Command::ListTrash,
+                    Some("restore") => match args.next() {
+                        Some(item) => Command::RestoreTrash(item),
+                        None => return Err(ConfigError::CommandError("No password id or name given".to_string())),
+                    },
+                    Some("empty") => match args.next().as_deref() {
+                        None => Command::EmptyTrash { yes: false },
+                        Some("-y" | "--yes") => Command::EmptyTrash { yes: true },
+                        Some(arg) => return Err(unknown_option(arg)),
+                    },
+                    Some(flag @ "keep") => match parse_number(flag, &mut args)? {
+                        days if days > trash::MAX_KEEP_DAYS => {
+                            return Err(ConfigError::CommandError(format!("`{flag}` can be at most {} days", trash::MAX_KEEP_DAYS)))
+                        },
+                        days => Command::KeepTrash(days),
+                    },
+                    Some(arg) => return Err(ConfigError::CommandError(format!("Unknown trash command `{arg}`"))),
+                },
                 _ => return Err(ConfigError::CommandError("Command option does not exist".to_string())),
             },
             None => return Err(ConfigError::CommandError("Didn't get a command".to_string())),
         };
 
         Ok(Config {
-            database_name: String::from(database_name),
+            database_name,
             command,
+            lock_timeout,
+            secret_source,
+            min_master_score,
         })
     }
 }
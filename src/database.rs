@@ -1,186 +1,838 @@
-use std::fs;
+use std::{fs::{self, File}, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sha2::{Sha256, Digest};
 use thiserror::Error;
+use uuid::Uuid;
 
-use crate::{config::Command, password::{Password, PasswordError}};
+use crate::{
+    atomic,
+    attachment::{self, AttachmentError},
+    audit::{self, Report},
+    backup,
+    breach::{BreachError, BreachList},
+    cipher::{self, CipherError},
+    config::{Command, LayoutOptions, ListOptions, SortKey},
+    folder,
+    format::{self, FormatError, Header, Layout, FORMAT_VERSION},
+    kdf::KdfParams,
+    keyslot::{self, KeySlot, KeySlotError, KeySlotKind},
+    lock::{self, DatabaseLock, LockError, LockMode},
+    migration::{self, MigrationContext, MigrationError},
+    otp::{Code, Otp, OtpError},
+    password::{self, Attachment, Password, PasswordError},
+    search::{self, MatchKind},
+    strength,
+    trash,
+};
+
+// Shorter id prefixes are too likely to be mistaken for a name
+const MIN_PREFIX_LEN: usize = 4;
 
 #[derive(Serialize, Deserialize)]
 pub struct Database {
-    pub master_password: Vec<u8>,
+    // Written ahead of the JSON body, see `format::encode`
+    #[serde(skip)]
+    pub header: Header,
     pub passwords: Vec<Password>,
+    // Deleted passwords, kept for `header.trash_days`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trash: Vec<Password>,
+    // Holds the body of a sealed database until it is unlocked
+    #[serde(skip)]
+    sealed_body: Option<SealedBody>,
+}
+
+struct SealedBody {
+    // The header exactly as read from the file, it is authenticated along with the body
+    header: Vec<u8>,
+    ciphertext: Vec<u8>,
 }
 
 impl Database {
-    pub fn new(name: String, master_password: String) -> Result<(), DatabaseError>{
-        let mut hasher = Sha256::new();
-        hasher.update(master_password.as_bytes());
-        let master_password_hashed = hasher.finalize();
+    pub fn create(file_path: String, master_password: &str, kdf_params: KdfParams, layout_options: LayoutOptions) -> Result<(), DatabaseError> {
+        let data_key = keyslot::generate_data_key();
 
-        let database = Database {
-            master_password: master_password_hashed.to_vec(),
+        let mut database = Database {
+            header: Header::default(),
             passwords: vec![],
+            trash: vec![],
+            sealed_body: None,
         };
+        database.header.key_slots.push(KeySlot::wrap_password(&data_key, master_password, kdf_params)?);
+        layout_options.apply(&mut database.header);
 
-        database.save(name + &String::from(".oxd"))
+        database.save(file_path, &data_key)
     }
 
     pub fn load(file_path: &String) -> Result<Database, DatabaseError> {
-        let raw_contents = match fs::read_to_string(file_path) {
+        let raw_contents = match fs::read(file_path) {
             Ok(content) => content,
             Err(_) => return Err(DatabaseError::LoadError("Could not open database file".to_string())),
         };
 
-        let database: Database = match serde_json::from_str(&raw_contents) {
+        Database::decode(&raw_contents)
+    }
+
+    fn decode(raw_contents: &[u8]) -> Result<Database, DatabaseError> {
+        let (header, body) = format::decode(raw_contents)?;
+
+        // Sealed bodies can't be read until the master password is known, see `unlock`
+        if header.layout == Layout::Sealed {
+            return Ok(Database {
+                header,
+                passwords: vec![],
+                trash: vec![],
+                sealed_body: Some(SealedBody {
+                    header: raw_contents[..raw_contents.len() - body.len()].to_vec(),
+                    ciphertext: body.to_vec(),
+                }),
+            });
+        }
+
+        let mut database: Database = match serde_json::from_slice(body) {
             Ok(db) => db,
             Err(_) => return Err(DatabaseError::LoadError("Could not deserialize database JSON".to_string())),
         };
+        database.header = header;
 
         Ok(database)
     }
 
-    pub fn save(&self, file_path: String) -> Result<(), DatabaseError> {
-        let database_serialized =  serde_json::json!(&self).to_string();
-        Ok(fs::write(file_path, database_serialized)?)
+    pub fn save(&self, file_path: String, encryption_key: &[u8]) -> Result<(), DatabaseError> {
+        let contents = self.encode(encryption_key)?;
+        backup::rotate(&file_path, self.header.backups)?;
+        Ok(atomic::write(file_path, &contents)?)
+    }
+
+    // For changes not worth keeping a backup from before, like recording when a password
+    // was last used
+    fn save_without_backup(&self, file_path: String, encryption_key: &[u8]) -> Result<(), DatabaseError> {
+        let contents = self.encode(encryption_key)?;
+        Ok(atomic::write(file_path, &contents)?)
+    }
+
+    fn encode(&self, encryption_key: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        let database_serialized = serde_json::json!(&self).to_string();
+
+        let contents = match self.header.layout {
+            Layout::Entries => format::encode(&self.header, database_serialized.as_bytes())?,
+            Layout::Sealed => {
+                let mut body = database_serialized.into_bytes();
+                format::pad(&mut body, self.header.padding);
+
+                let mut contents = format::encode(&self.header, &[])?;
+                let sealed = cipher::seal(encryption_key, &body, &contents)?;
+                contents.extend(sealed);
+                contents
+            },
+        };
+
+        Ok(contents)
+    }
+
+    // Hold the returned lock for as long as the database is in use, see `lock::acquire`
+    pub fn lock(file_path: &str, mode: LockMode, timeout: Duration) -> Result<DatabaseLock, DatabaseError> {
+        Ok(lock::acquire(file_path, mode, timeout)?)
+    }
+
+    pub fn format_version(file_path: &String) -> Result<u16, DatabaseError> {
+        let raw_contents = match fs::read(file_path) {
+            Ok(content) => content,
+            Err(_) => return Err(DatabaseError::LoadError("Could not open database file".to_string())),
+        };
+
+        Ok(format::detect_version(&raw_contents)?)
+    }
+
+    // Rewrite a database from an older format version into the current one, the original
+    // file is copied aside first and the path of the copy is returned
+    pub fn migrate(file_path: &String, master_password: &str) -> Result<String, DatabaseError> {
+        let raw_contents = fs::read(file_path)?;
+        let version = format::detect_version(&raw_contents)?;
+        let migrated = migration::migrate(&raw_contents, &MigrationContext {
+            master_password,
+            kdf_params: KdfParams::default(),
+        })?;
+
+        let mut backup_path = format!("{file_path}.v{version}.bak");
+        let mut backup_count = 0;
+        while Path::new(&backup_path).exists() {
+            backup_count += 1;
+            backup_path = format!("{file_path}.v{version}.bak.{backup_count}");
+        }
+        fs::copy(file_path, &backup_path)?;
+        atomic::write(file_path, &migrated)?;
+
+        Ok(backup_path)
+    }
+
+    // Put backup `number` back in place of the database, which becomes the newest backup
+    // itself. Returns false without touching anything if the password doesn't open the
+    // backup, and fails if any of its passwords can't be decrypted.
+    pub fn restore_backup(file_path: &str, number: u32, master_password: &str) -> Result<bool, DatabaseError> {
+        let Ok(raw_contents) = fs::read(backup::backup_path(file_path, number)) else {
+            return Err(DatabaseError::CommandError(format!("There is no backup {number}, use `backups list` to see the available backups")));
+        };
+
+        // Backups made before a format change are checked through a migrated copy, but
+        // restored as they are and migrated the next time the database is opened
+        let current_contents = if format::detect_version(&raw_contents)? < FORMAT_VERSION {
+            let context = MigrationContext { master_password, kdf_params: KdfParams::default() };
+            match migration::migrate(&raw_contents, &context) {
+                Err(MigrationError::IncorrectPassword) => return Ok(false),
+                result => result?,
+            }
+        } else {
+            raw_contents.clone()
+        };
+
+        let mut database = Database::decode(&current_contents)?;
+        let Some(encryption_key) = database.unlock(master_password)? else {
+            return Ok(false);
+        };
+        for password in database.passwords.iter().chain(&database.trash) {
+            password.decrypt(&encryption_key)?;
+        }
+
+        backup::rotate(file_path, database.header.backups)?;
+        atomic::write(file_path, &raw_contents)?;
+        Ok(true)
+    }
+
+    pub fn set_backups(mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::KeepBackups(keep) => self.header.backups = keep,
+            _ => panic!("Expected `Command::KeepBackups`, got a different Command variant"),
+        }
+
+        self.save(file_path, encryption_key)?;
+        Ok(())
     }
 
-    pub fn change_master_password(mut self, file_path: String, old_password: &String, cmd: Command) -> Result<(), DatabaseError> {
+    // Only the key slot is rewrapped, the passwords stay encrypted with the same data key
+    pub fn change_master_password(mut self, file_path: String, data_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
         match cmd {
             Command::ChangeMaster(new_password) => {
-                let new_password = if new_password.is_none() {
+                let Some(new_password) = new_password else {
                     return Err(DatabaseError::CommandError("No new password was supplied for the master password".to_string()));
-                } else {
-                    new_password.unwrap()
                 };
-                let mut hasher = Sha256::new();
-                hasher.update(new_password.as_bytes());
-                let master_password_hashed = hasher.finalize().to_vec();
+                let password_slot = self.password_slot()?;
+                *password_slot = KeySlot::wrap_password(data_key, &new_password, password_slot.kdf.params)?;
+            },
+            _ => panic!("Expected `Command::ChangeMaster, got a different Command variant"),
+        }
 
-                self.master_password = master_password_hashed;
+        self.save(file_path, data_key)?;
+        Ok(())
+    }
 
-                self.passwords = self.passwords
-                                    .into_iter()
-                                    .map(|password| password
-                                        .update_encryption_key(old_password, &new_password).unwrap()).collect::<Vec<Password>>();
+    pub fn update_kdf(mut self, file_path: String, data_key: &[u8], master_password: &str, cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::UpdateKdf(options) => {
+                let password_slot = self.password_slot()?;
+                *password_slot = KeySlot::wrap_password(data_key, master_password, options.apply(password_slot.kdf.params))?;
             },
-            _ => panic!("Expected `Command::ChangeMaster, got a different Command variant"),
+            _ => panic!("Expected `Command::UpdateKdf`, got a different Command variant"),
         }
 
-        self.save(file_path)?;
+        self.save(file_path, data_key)?;
         Ok(())
     }
 
-    pub fn list_passwords(&self, decryption_key: &String) -> Result<(), PasswordError> {
-        let mut password_count = 0;
-        for password in &self.passwords {
-            let decrypted_password = password.decrypt(decryption_key)?;
-            println!("{id}. {name} - {user}",
-                id = password_count,
-                name = decrypted_password.name,
-                user = decrypted_password.username
-            );
-            password_count += 1;
+    fn password_slot(&mut self) -> Result<&mut KeySlot, DatabaseError> {
+        match self.header.key_slots.iter_mut().find(|slot| slot.kind == KeySlotKind::Password) {
+            Some(slot) => Ok(slot),
+            None => Err(DatabaseError::LoadError("Database has no master password key slot".to_string())),
+        }
+    }
+
+    pub fn set_layout(mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::SetLayout(options) => options.apply(&mut self.header),
+            _ => panic!("Expected `Command::SetLayout`, got a different Command variant"),
+        }
+
+        self.save(file_path, encryption_key)?;
+        Ok(())
+    }
+
+    pub fn list_passwords(&self, decryption_key: &[u8], options: &ListOptions) -> Result<(), PasswordError> {
+        let passwords = self.select_passwords(decryption_key, options, Utc::now())?;
+        if options.tree {
+            let lines = passwords.into_iter().map(|password| {
+                let line = describe(&password, false);
+                (password.folder, line)
+            });
+            print!("{}", folder::render_tree(lines.collect()));
+            return Ok(());
+        }
+
+        for password in passwords {
+            println!("{}", describe(&password, true));
         }
         Ok(())
     }
 
+    // Passwords without a timestamp sort before all the others, and are always old enough
+    // for `older_than` since they were saved before timestamps were recorded
+    fn select_passwords(&self, decryption_key: &[u8], options: &ListOptions, now: DateTime<Utc>) -> Result<Vec<Password>, PasswordError> {
+        let mut passwords = self.passwords
+            .iter()
+            .map(|password| password.decrypt(decryption_key))
+            .collect::<Result<Vec<Password>, PasswordError>>()?;
+
+        if let Some(older_than) = options.older_than {
+            passwords.retain(|password| match password.password_changed.as_deref().and_then(password::parse_timestamp) {
+                Some(changed) => now.signed_duration_since(changed) > older_than,
+                None => true,
+            });
+        }
+
+        match &options.folder {
+            Some(Some(filter)) => passwords.retain(|password| folder::in_folder(password.folder.as_deref(), filter)),
+            Some(None) => passwords.retain(|password| password.folder.is_none()),
+            None => (),
+        }
+        passwords.retain(|password| options.tags.iter().all(|tag| folder::has_tag(&password.tags, tag)));
+        if let Some(kind) = options.kind {
+            passwords.retain(|password| password.kind == kind);
+        }
+
+        let timestamp = |timestamp: &Option<String>| timestamp.as_deref().and_then(password::parse_timestamp);
+        match options.sort {
+            None => (),
+            Some(SortKey::Name) => passwords.sort_by_key(|password| password.name.to_lowercase()),
+            Some(SortKey::Created) => passwords.sort_by_key(|password| timestamp(&password.created)),
+            Some(SortKey::Modified) => passwords.sort_by_key(|password| timestamp(&password.modified)),
+            Some(SortKey::Changed) => passwords.sort_by_key(|password| timestamp(&password.password_changed)),
+            Some(SortKey::Accessed) => passwords.sort_by_key(|password| timestamp(&password.last_accessed)),
+        }
+        Ok(passwords)
+    }
+
+    // Passwords with an expiry date or rotation interval that are due by `within` from now,
+    // with when they are due, the longest overdue first
+    pub fn due_passwords(&self, decryption_key: &[u8], cmd: Command) -> Result<Vec<(DateTime<Utc>, Password)>, DatabaseError> {
+        match cmd {
+            Command::Due { within } => Ok(self.select_due(decryption_key, within, Utc::now())?),
+            _ => panic!("Expected `Command::Due`, got a different Command variant"),
+        }
+    }
+
+    fn select_due(&self, decryption_key: &[u8], within: chrono::Duration, now: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, Password)>, PasswordError> {
+        let mut due = vec![];
+        for password in &self.passwords {
+            let password = password.decrypt(decryption_key)?;
+            // Looking further ahead than dates go finds everything that is due at all
+            if let Some(date) = password.due().filter(|date| now.checked_add_signed(within).is_none_or(|by| *date <= by)) {
+                due.push((date, password));
+            }
+        }
+        due.sort_by_key(|(date, _)| *date);
+        Ok(due)
+    }
+
+    // Every password is decrypted once and checked against all the others. Only logins are
+    // audited, the other kinds don't use their password.
+    pub fn audit_passwords(&self, decryption_key: &[u8], max_age_days: u32, breaches: Option<&mut BreachList>) -> Result<Report, DatabaseError> {
+        let mut passwords = self.passwords
+            .iter()
+            .map(|password| password.decrypt(decryption_key))
+            .collect::<Result<Vec<Password>, PasswordError>>()?;
+        passwords.retain(|password| password.kind.is_login());
+        Ok(audit::audit(&passwords, Utc::now(), chrono::Duration::days(max_age_days.into()), breaches)?)
+    }
+
     // For any new information, the aim is to immediately encrypt and store it
-    pub fn new_password(&mut self, file_path: String, encryption_key: String, cmd: Command) -> Result<(), DatabaseError> {
+    pub fn new_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
         match cmd {
-            Command::New { name, user, pass } => {
-                let name = if name.is_none() {
+            Command::New { name, kind, user, pass, fields, .. } => {
+                let Some(name) = name else {
                     return Err(DatabaseError::CommandError("No name was supplied for the password, so the password was not made".to_string()));
-                } else {
-                    name.unwrap()
                 };
 
-                let username = if user.is_none() { String::from("") } else { user.unwrap() };
+                let username = user.unwrap_or_default();
 
-                let password = if pass.is_none() { String::from("") } else { pass.unwrap() };
+                let password = pass.unwrap_or_default();
 
-                self.passwords.push(Password {
-                    name,
-                    username,
-                    password,
-                }.encrypt(encryption_key));
+                let mut password = Password::new(name, username, password);
+                password.kind = kind;
+                fields.apply(&mut password).map_err(DatabaseError::CommandError)?;
+                if let Some(detail) = kind.details().iter().find(|detail| detail.required && !password.details.contains_key(detail.key)) {
+                    return Err(DatabaseError::CommandError(format!("A {kind} needs a {}, give it with --detail {}", detail.label.to_lowercase(), detail.key)));
+                }
+                if kind.is_login() {
+                    warn_if_weak(&password);
+                }
+                self.passwords.push(password.encrypt(encryption_key)?);
             },
             _ => panic!("Expected `Command::New`, got a different Command variant"),
         }
 
-        self.save(file_path)?;
+        self.save(file_path, encryption_key)?;
         Ok(())
     }
 
-    pub fn edit_password(&mut self, file_path: String, encryption_key: String, cmd: Command) -> Result<(), DatabaseError> {
+    pub fn edit_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
         match cmd {
-            Command::Edit { item, name, user, pass } => {
-                if item.is_none() {
-                    return Err(DatabaseError::CommandError("Invalid password id given, so no password was edited".to_string()));
-                } else {
-                    let password_id = item.unwrap();
-                    if password_id >= self.passwords.len() {
-                        return Err(DatabaseError::CommandError(format!("The id supplied does not exist in the database, valid id's are 0-{}",self.passwords.len()-1)));
+            Command::Edit { item, name, user, pass, fields, .. } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let encrypted_password = &self.passwords[password_id];
+                let mut decrypted_password = encrypted_password.decrypt(encryption_key)?;
+                let mut modified = name.is_some() || user.is_some() || pass.is_some();
+                modified |= fields.apply(&mut decrypted_password).map_err(DatabaseError::CommandError)?;
+                if let Some(name) = name { decrypted_password.name = name; }
+                if let Some(user) = user { decrypted_password.username = user; }
+                if let Some(pass) = pass {
+                    decrypted_password.set_password(pass);
+                    if decrypted_password.kind.is_login() {
+                        warn_if_weak(&decrypted_password);
                     }
-                    let encrypted_password = &self.passwords[password_id];
-                    let mut decrypted_password = encrypted_password.decrypt(&encryption_key).unwrap();
-                    if !name.is_none() { decrypted_password.name = name.unwrap(); }
-                    if !user.is_none() { decrypted_password.username = user.unwrap(); }
-                    if !pass.is_none() { decrypted_password.password = pass.unwrap(); }
-                    self.passwords[item.unwrap()] = decrypted_password.encrypt(encryption_key);
                 }
+                if modified { decrypted_password.touch_modified(); }
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
             },
             _ => panic!("Expected `Command::Delete`, got a different Command variant"),
         }
         
-        self.save(file_path)?;
+        self.save(file_path, encryption_key)?;
         Ok(())
     }
 
-    pub fn del_password(&mut self, file_path: String, cmd: Command) -> Result<(), DatabaseError> {
+    // Deleted passwords go to the trash, see `restore_from_trash`
+    pub fn del_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
         match cmd {
-            Command::Delete(id) => {
-                if id.is_none() {
-                    return Err(DatabaseError::CommandError("Invalid password id given, so no password was deleted".to_string()));
-                } else {
-                    let password_id = id.unwrap();
-                    if password_id >= self.passwords.len() {
-                        return Err(DatabaseError::CommandError(format!("The id supplied does not exist in the database, valid id's are 0-{}",self.passwords.len()-1)));
-                    }
-                    self.passwords.remove(password_id);
-                }
+            Command::Delete { item, .. } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut password = self.passwords.remove(password_id);
+                password.mark_deleted(encryption_key)?;
+                self.trash.push(password);
             },
             _ => panic!("Expected `Command::Delete`, got a different Command variant"),
         }
+        let purged = self.purge_trash(encryption_key, Utc::now())?;
         
-        self.save(file_path)?;
+        self.save(file_path.clone(), encryption_key)?;
+        remove_attachments(&file_path, &purged)
+    }
+
+    pub fn list_trash(&self, decryption_key: &[u8]) -> Result<(), PasswordError> {
+        let now = Utc::now();
+        for password in &self.trash {
+            let decrypted_password = password.decrypt(decryption_key)?;
+            if trash::is_expired(decrypted_password.deleted.as_deref(), self.header.trash_days, now) {
+                continue;
+            }
+            let expires = decrypted_password.deleted
+                .as_deref()
+                .and_then(|deleted| trash::expires(deleted, self.header.trash_days))
+                .map_or("the trash is emptied".to_string(), password::format_time);
+            println!("{id}  {name} - {user}  (deleted {deleted}, removed after {expires})",
+                id = password.short_id(),
+                name = decrypted_password.name,
+                user = decrypted_password.username,
+                deleted = password::format_timestamp(&decrypted_password.deleted, "unknown"),
+                expires = expires
+            );
+        }
         Ok(())
     }
 
-    pub fn get_password(&self, decryption_key: &String, cmd: Command) -> Result<Password, DatabaseError> {
+    pub fn restore_from_trash(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        let purged = match cmd {
+            Command::RestoreTrash(item) => {
+                let purged = self.purge_trash(encryption_key, Utc::now())?;
+                let trash_id = find_entry(&self.trash, encryption_key, &item, "trash list")?;
+                let mut password = self.trash.remove(trash_id);
+                password.deleted = None;
+                self.passwords.push(password);
+                purged
+            },
+            _ => panic!("Expected `Command::RestoreTrash`, got a different Command variant"),
+        };
+
+        self.save(file_path.clone(), encryption_key)?;
+        remove_attachments(&file_path, &purged)
+    }
+
+    pub fn empty_trash(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        let emptied = match cmd {
+            Command::EmptyTrash { .. } => std::mem::take(&mut self.trash),
+            _ => panic!("Expected `Command::EmptyTrash`, got a different Command variant"),
+        };
+
+        self.save(file_path.clone(), encryption_key)?;
+        remove_attachments(&file_path, &emptied)?;
+        self.sweep_attachments(&file_path)
+    }
+
+    pub fn set_trash_days(mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::KeepTrash(days) => self.header.trash_days = days,
+            _ => panic!("Expected `Command::KeepTrash`, got a different Command variant"),
+        }
+
+        let purged = self.purge_trash(encryption_key, Utc::now())?;
+        self.save(file_path.clone(), encryption_key)?;
+        remove_attachments(&file_path, &purged)
+    }
+
+    // Remove passwords that have been in the trash for longer than `header.trash_days`,
+    // returning them so their attachments can be removed once the database is saved
+    fn purge_trash(&mut self, decryption_key: &[u8], now: DateTime<Utc>) -> Result<Vec<Password>, PasswordError> {
+        let mut kept = vec![];
+        let mut purged = vec![];
+        for password in self.trash.drain(..) {
+            let deleted = password.decrypt(decryption_key)?.deleted;
+            if trash::is_expired(deleted.as_deref(), self.header.trash_days, now) {
+                purged.push(password);
+            } else {
+                kept.push(password);
+            }
+        }
+        self.trash = kept;
+        Ok(purged)
+    }
+
+    pub fn get_password(&self, decryption_key: &[u8], cmd: Command) -> Result<Password, DatabaseError> {
+        match cmd {
+            Command::Get { item, .. } => {
+                let password_id = self.find_password(decryption_key, &item)?;
+                Ok(self.passwords[password_id].decrypt(decryption_key)?)
+            },
+            _ => panic!("Expected `Command::Delete`, got a different Command variant"),
+        }
+    }
+
+    // The entry is returned with its previous passwords, see `Password::history`
+    pub fn get_history(&self, decryption_key: &[u8], cmd: Command) -> Result<Password, DatabaseError> {
+        match cmd {
+            Command::History(item) => {
+                let password_id = self.find_password(decryption_key, &item)?;
+                Ok(self.passwords[password_id].decrypt(decryption_key)?)
+            },
+            _ => panic!("Expected `Command::History`, got a different Command variant"),
+        }
+    }
+
+    pub fn restore_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::Restore { item, number } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                if decrypted_password.restore_previous(number as usize).is_none() {
+                    return Err(DatabaseError::CommandError(format!(
+                        "There is no previous password {number} for {}, it has {} previous passwords",
+                        decrypted_password.name,
+                        decrypted_password.history.len()
+                    )));
+                }
+                decrypted_password.touch_modified();
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
+            },
+            _ => panic!("Expected `Command::Restore`, got a different Command variant"),
+        }
+
+        self.save(file_path, encryption_key)
+    }
+
+    pub fn move_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        match cmd {
+            Command::Move { item, folder } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                decrypted_password.folder = folder;
+                decrypted_password.touch_modified();
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
+            },
+            _ => panic!("Expected `Command::Move`, got a different Command variant"),
+        }
+
+        self.save(file_path, encryption_key)
+    }
+
+    pub fn tag_password(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
         match cmd {
-            Command::Get(id) => {
-                if id.is_none() {
-                    return Err(DatabaseError::CommandError("Invalid password id given, so no password was fetched".to_string()));
-                } else {
-                    let password_id = id.unwrap();
-                    if password_id >= self.passwords.len() {
-                        return Err(DatabaseError::CommandError(format!("The id supplied does not exist in the database, valid id's are 0-{}",self.passwords.len()-1)));
+            Command::Tag { item, add, remove } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                if let Some(tag) = remove.iter().find(|tag| !folder::has_tag(&decrypted_password.tags, tag)) {
+                    return Err(DatabaseError::CommandError(format!("{} has no tag {tag}", decrypted_password.name)));
+                }
+                decrypted_password.tags.retain(|tag| !folder::has_tag(&remove, tag));
+                for tag in add {
+                    if !folder::has_tag(&decrypted_password.tags, &tag) {
+                        decrypted_password.tags.push(tag);
                     }
-                    Ok(self.passwords[password_id].decrypt(decryption_key)?)
                 }
+                decrypted_password.touch_modified();
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
             },
-            _ => panic!("Expected `Command::Delete`, got a different Command variant"),
+            _ => panic!("Expected `Command::Tag`, got a different Command variant"),
+        }
+
+        self.save(file_path, encryption_key)
+    }
+
+    // The file is encrypted into its own file before the database is saved, and removed
+    // again if saving fails. One left behind by a crash is removed by `sweep_attachments`.
+    pub fn attach_file(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<Attachment, DatabaseError> {
+        let attachment = match cmd {
+            Command::Attach { item, file, name } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                let name = match name {
+                    Some(name) => name,
+                    None => Path::new(&file).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+                };
+                let name = attachment::check_name(&name)?;
+                if decrypted_password.attachments.iter().any(|attachment| attachment.name == name) {
+                    return Err(DatabaseError::CommandError(format!("{} already has an attachment named \"{name}\", detach it first or give another name with --name", decrypted_password.name)));
+                }
+
+                let source = File::open(&file).map_err(|e| DatabaseError::CommandError(format!("Failed to open {file}: {e}")))?;
+                // Checked again while it is read, in case it isn't a regular file or grows
+                if source.metadata()?.len() > attachment::MAX_SIZE {
+                    return Err(AttachmentError::TooLarge(attachment::MAX_SIZE).into());
+                }
+                let id = Uuid::new_v4();
+                let size = attachment::store(&file_path, encryption_key, &id, source, attachment::MAX_SIZE)?;
+                decrypted_password.add_attachment(id, name, size);
+                let attachment = decrypted_password.attachments.last().cloned().unwrap_or_default();
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
+                attachment
+            },
+            _ => panic!("Expected `Command::Attach`, got a different Command variant"),
+        };
+
+        if let Err(e) = self.save(file_path.clone(), encryption_key) {
+            let _ = attachment::remove(&file_path, &attachment.id);
+            return Err(e);
+        }
+        Ok(attachment)
+    }
+
+    // The entry is returned with its attachments, see `Password::attachments`
+    pub fn get_attachments(&self, decryption_key: &[u8], cmd: Command) -> Result<Password, DatabaseError> {
+        match cmd {
+            Command::Attachments(item) => {
+                let password_id = self.find_password(decryption_key, &item)?;
+                Ok(self.passwords[password_id].decrypt(decryption_key)?)
+            },
+            _ => panic!("Expected `Command::Attachments`, got a different Command variant"),
+        }
+    }
+
+    // Find the attachment to extract, the file is decrypted by `attachment::extract`
+    pub fn get_attachment(&self, decryption_key: &[u8], cmd: Command) -> Result<Attachment, DatabaseError> {
+        match cmd {
+            Command::Extract { item, name, .. } => {
+                let password_id = self.find_password(decryption_key, &item)?;
+                let decrypted_password = self.passwords[password_id].decrypt(decryption_key)?;
+                find_attachment(&decrypted_password, &name).map(|position| decrypted_password.attachments[position].clone())
+            },
+            _ => panic!("Expected `Command::Extract`, got a different Command variant"),
         }
     }
 
-    pub fn verify_master_password(&self, entered_password: &String) -> bool {
-        let mut hasher = Sha256::new();
-        hasher.update(entered_password.as_bytes());
-        let entered_password_hashed = hasher.finalize().to_vec();
+    // The file is removed once the database no longer refers to it, older backups still
+    // list the attachment but can't extract it
+    pub fn detach_file(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<(), DatabaseError> {
+        let attachment = match cmd {
+            Command::Detach { item, name } => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                let position = find_attachment(&decrypted_password, &name)?;
+                let attachment = decrypted_password.attachments.remove(position);
+                decrypted_password.touch_modified();
+                self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
+                attachment
+            },
+            _ => panic!("Expected `Command::Detach`, got a different Command variant"),
+        };
+
+        self.save(file_path.clone(), encryption_key)?;
+        attachment::remove(&file_path, &attachment.id)?;
+        self.sweep_attachments(&file_path)
+    }
+
+    // Files are stored before the database that lists them is saved, so a crash in between
+    // leaves files nothing refers to. They are swept up when attachments are removed.
+    fn sweep_attachments(&self, file_path: &str) -> Result<(), DatabaseError> {
+        let referenced = self.passwords
+            .iter()
+            .chain(&self.trash)
+            .flat_map(|password| &password.attachments)
+            .map(|attachment| attachment.id)
+            .collect();
+        Ok(attachment::sweep(file_path, &referenced)?)
+    }
+
+    // HOTP codes move the counter on, which is saved without a backup like `record_access`
+    pub fn otp_code(&mut self, file_path: String, encryption_key: &[u8], cmd: Command) -> Result<Code, DatabaseError> {
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        match cmd {
+            Command::Otp(item) => {
+                let password_id = self.find_password(encryption_key, &item)?;
+                let mut decrypted_password = self.passwords[password_id].decrypt(encryption_key)?;
+                let Some(uri) = &decrypted_password.otp else {
+                    return Err(DatabaseError::CommandError(format!("{} has no one-time password, add one with edit --otp", decrypted_password.name)));
+                };
+                let mut otp = Otp::parse(uri)?;
+                let code = otp.code(unix_time)?;
+                if code.counter.is_some() {
+                    decrypted_password.otp = Some(otp.to_uri());
+                    self.passwords[password_id] = decrypted_password.encrypt(encryption_key)?;
+                    self.save_without_backup(file_path, encryption_key)?;
+                }
+                Ok(code)
+            },
+            _ => panic!("Expected `Command::Otp`, got a different Command variant"),
+        }
+    }
+
+    // Called after `get_password`, without a backup so that looking at passwords doesn't
+    // push older changes out of the backups
+    pub fn record_access(&mut self, file_path: String, encryption_key: &[u8], id: Uuid) -> Result<(), DatabaseError> {
+        let Some(password) = self.passwords.iter_mut().find(|password| password.id == id) else {
+            return Err(DatabaseError::CommandError(format!("No password has the id {id}")));
+        };
+        password.touch_accessed(encryption_key)?;
+
+        self.save_without_backup(file_path, encryption_key)
+    }
+
+    // Find the position of the password `query` refers to, either by its full id, a unique
+    // prefix of its id, or by searching names and usernames. Only the closest kind of match
+    // found is considered, see `search::MatchKind`.
+    pub fn find_password(&self, decryption_key: &[u8], query: &str) -> Result<usize, DatabaseError> {
+        find_entry(&self.passwords, decryption_key, query, "list")
+    }
+
+    // Try the entered password against each key slot, returning the data key used to
+    // encrypt the passwords if one of them opens
+    pub fn unlock(&mut self, entered_password: &str) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let mut data_key = None;
+        for slot in self.header.key_slots.iter().filter(|slot| slot.kind == KeySlotKind::Password) {
+            data_key = slot.unwrap_password(entered_password)?;
+            if data_key.is_some() {
+                break;
+            }
+        }
+        let Some(data_key) = data_key else {
+            return Ok(None);
+        };
+
+        if let Some(sealed_body) = &self.sealed_body {
+            let body = cipher::open(&data_key, &sealed_body.ciphertext, &sealed_body.header)?;
+            let database: Database = match serde_json::from_slice(&body) {
+                Ok(db) => db,
+                Err(_) => return Err(DatabaseError::LoadError("Could not deserialize database JSON".to_string())),
+            };
+            self.passwords = database.passwords;
+            self.trash = database.trash;
+            self.sealed_body = None;
+        }
+
+        Ok(Some(data_key))
+    }
+
+}
+
+// One line of the list command, the folder is left out when the line goes in a tree
+fn describe(password: &Password, with_folder: bool) -> String {
+    let folder = match &password.folder {
+        Some(folder) if with_folder => format!("{folder}/"),
+        _ => String::new(),
+    };
+    let tags: String = password.tags.iter().map(|tag| format!(" #{tag}")).collect();
+    let kind = match password.kind {
+        kind if kind.is_login() => String::new(),
+        kind => format!("[{kind}] "),
+    };
+    // Overdue passwords stand out at the end of the line
+    let overdue = match password.due() {
+        Some(due) if due <= Utc::now() => format!("  OVERDUE since {}", password::format_date(due)),
+        _ => String::new(),
+    };
+    format!("{id}  {kind}{folder}{name} - {user}{tags}  (modified {modified}, last used {used}){overdue}",
+        id = password.short_id(),
+        name = password.name,
+        user = password.username,
+        modified = password::format_timestamp(&password.modified, "unknown"),
+        used = password::format_timestamp(&password.last_accessed, "never")
+    )
+}
+
+fn find_attachment(password: &Password, name: &str) -> Result<usize, DatabaseError> {
+    password.attachments.iter().position(|attachment| attachment.name == name).ok_or_else(|| {
+        let names: Vec<&str> = password.attachments.iter().map(|attachment| attachment.name.as_str()).collect();
+        DatabaseError::CommandError(if names.is_empty() {
+            format!("{} has no attachments", password.name)
+        } else {
+            format!("{} has no attachment named \"{name}\", it has {}", password.name, names.join(", "))
+        })
+    })
+}
+
+// Remove the files attached to entries that have been removed for good, after the database
+// no longer refers to them. Entries from the database are still encrypted, but attachment
+// ids are readable.
+fn remove_attachments(file_path: &str, removed: &[Password]) -> Result<(), DatabaseError> {
+    for attachment in removed.iter().flat_map(|password| &password.attachments) {
+        attachment::remove(file_path, &attachment.id)?;
+    }
+    Ok(())
+}
+
+// See `Database::find_password`, `list_command` is the command that lists `entries`
+fn find_entry(entries: &[Password], decryption_key: &[u8], query: &str, list_command: &str) -> Result<usize, DatabaseError> {
+    if let Ok(id) = Uuid::parse_str(query) {
+        if let Some(password_id) = entries.iter().position(|password| password.id == id) {
+            return Ok(password_id);
+        }
+    }
+
+    let prefix = query.replace('-', "").to_lowercase();
+    let is_prefix = prefix.len() >= MIN_PREFIX_LEN && prefix.chars().all(|c| c.is_ascii_hexdigit());
+
+    let mut best_kind = None;
+    let mut matches = vec![];
+    for (password_id, password) in entries.iter().enumerate() {
+        let decrypted_password = password.decrypt(decryption_key)?;
+        // An id prefix is nearly as deliberate as typing the exact name, which still wins so
+        // a name made of hex digits can't be taken over by another entry's id
+        let id_kind = (is_prefix && password.id.simple().to_string().starts_with(&prefix)).then_some(MatchKind::IdPrefix);
+        let kind = [&decrypted_password.name, &decrypted_password.username]
+            .into_iter()
+            .filter_map(|field| search::match_kind(query, field))
+            .chain(id_kind)
+            .min();
 
-        entered_password_hashed == self.master_password
+        let Some(kind) = kind else { continue };
+        if best_kind.is_some_and(|best_kind| kind > best_kind) {
+            continue;
+        }
+        if best_kind != Some(kind) {
+            best_kind = Some(kind);
+            matches.clear();
+        }
+        matches.push((password_id, decrypted_password));
     }
 
+    match matches.len() {
+        1 => Ok(matches[0].0),
+        0 => Err(DatabaseError::CommandError(format!("No password matches \"{query}\", use the {list_command} command to find its id"))),
+        _ => Err(DatabaseError::AmbiguousMatch(query.to_string(), matches.into_iter().map(|(_, password)| password).collect())),
+    }
+}
+
+// Weak passwords are still saved, what a site accepts isn't always up to the user
+fn warn_if_weak(password: &Password) {
+    let strength = strength::estimate(&password.password, &[&password.name, &password.username]);
+    if strength.is_weak() {
+        eprintln!("Warning: the password for {} is weak, {strength}", password.name);
+    }
 }
 
 #[derive(Error, Debug)]
@@ -192,5 +844,537 @@ pub enum DatabaseError {
     #[error("`{0}`")]
     CommandError(String),
     #[error("failed to get the selected password")]
-    GetPasswordError(#[from] PasswordError)
-}
\ No newline at end of file
+    GetPasswordError(#[from] PasswordError),
+    #[error("failed to unlock the database key")]
+    KeySlot(#[from] KeySlotError),
+    #[error("{0}")]
+    Format(#[from] FormatError),
+    #[error("failed to migrate the database: {0}")]
+    Migration(#[from] MigrationError),
+    #[error("failed to encrypt or decrypt the database")]
+    Cipher(#[from] CipherError),
+    #[error("{0}")]
+    Lock(#[from] LockError),
+    #[error("{0}")]
+    Breach(#[from] BreachError),
+    #[error("{0}")]
+    Otp(#[from] OtpError),
+    #[error("{0}")]
+    Attachment(#[from] AttachmentError),
+    // Holds the decrypted candidates so the caller can ask which one was meant
+    #[error("\"{0}\" matches more than one password, give one of these ids instead:{}", describe_candidates(.1))]
+    AmbiguousMatch(String, Vec<Password>),
+}
+
+fn describe_candidates(candidates: &[Password]) -> String {
+    candidates
+        .iter()
+        .map(|password| format!("\n{id}  {name} - {user}", id = password.id, name = password.name, user = password.username))
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{config::FieldOptions, field::FieldKind, kind::EntryKind, password::CustomField};
+
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    // Make a database at `file_path` holding a single password
+    fn create(file_path: &str, layout_options: LayoutOptions) {
+        Database::create(file_path.to_string(), "testpass", TEST_PARAMS, layout_options).unwrap();
+
+        let mut database = Database::load(&file_path.to_string()).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let cmd = Command::New { name: Some("testname".to_string()), kind: EntryKind::Login, user: Some("testuser".to_string()), pass: Some("testpass".to_string()), generate: None, fields: FieldOptions::default() };
+        database.new_password(file_path.to_string(), &encryption_key, cmd).unwrap();
+    }
+
+    // A database from `create` with the default layout, unlocked. The directory it is in is
+    // removed when the `TempDir` is dropped.
+    fn unlocked_test_database() -> (TempDir, String, Database, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions::default());
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        (dir, file_path, database, encryption_key)
+    }
+
+    // A database that is never saved, for tests that only select from its entries
+    fn empty_database() -> Database {
+        Database { header: Header::default(), passwords: vec![], trash: vec![], sealed_body: None }
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions { layout: Some(Layout::Sealed), padding: Some(0) });
+
+        let mut database = Database::load(&file_path).unwrap();
+        assert!(database.passwords.is_empty());
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.name, "testname");
+    }
+
+    #[test]
+    fn sealed_hides_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions { layout: Some(Layout::Sealed), padding: Some(4096) });
+
+        let raw_contents = fs::read(&file_path).unwrap();
+        let (_, body) = format::decode(&raw_contents).unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(body).is_err());
+        // Padded to the bucket size, plus the nonce and tag
+        assert_eq!(body.len(), 4096 + cipher::NONCE_LEN + 16);
+    }
+
+    #[test]
+    fn sealed_wrong_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions { layout: Some(Layout::Sealed), padding: Some(0) });
+
+        let mut database = Database::load(&file_path).unwrap();
+        assert!(database.unlock("wrongpass").unwrap().is_none());
+    }
+
+    #[test]
+    fn sealed_rejects_modified_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions { layout: Some(Layout::Sealed), padding: Some(0) });
+
+        // Change the padding recorded in the header, which the body is authenticated against
+        let raw_contents = fs::read(&file_path).unwrap();
+        let (mut header, body) = format::decode(&raw_contents).unwrap();
+        header.padding = 1;
+        fs::write(&file_path, format::encode(&header, body).unwrap()).unwrap();
+
+        let mut database = Database::load(&file_path).unwrap();
+        assert!(matches!(database.unlock("testpass"), Err(DatabaseError::Cipher(CipherError::AuthenticationFailure))));
+    }
+
+    #[test]
+    fn change_master_password_keeps_passwords() {
+        let (_dir, file_path, database, encryption_key) = unlocked_test_database();
+        let encrypted_before = database.passwords[0].password.clone();
+        database.change_master_password(file_path.clone(), &encryption_key, Command::ChangeMaster(Some("newpass".to_string()))).unwrap();
+
+        let mut database = Database::load(&file_path).unwrap();
+        assert!(database.unlock("testpass").unwrap().is_none());
+        assert_eq!(database.unlock("newpass").unwrap(), Some(encryption_key.clone()));
+        // Only the key slot changed, the password itself was not re-encrypted
+        assert_eq!(database.passwords[0].password, encrypted_before);
+        assert_eq!(database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap().password, "testpass");
+    }
+
+    #[test]
+    fn save_rotates_backups() {
+        let (_dir, file_path, database, encryption_key) = unlocked_test_database();
+        database.set_backups(file_path.clone(), &encryption_key, Command::KeepBackups(1)).unwrap();
+
+        // The only backup kept is the database from before `set_backups`, with one password
+        let backups = backup::list(&file_path).unwrap();
+        assert_eq!(backups.len(), 1);
+        let mut backup = Database::load(&backups[0].path).unwrap();
+        assert_eq!(backup.header.backups, backup::DEFAULT_KEEP);
+        assert!(backup.unlock("testpass").unwrap().is_some());
+        assert_eq!(backup.passwords.len(), 1);
+    }
+
+    #[test]
+    fn restore_backup() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let before_delete = fs::read(&file_path).unwrap();
+        database.del_password(file_path.clone(), &encryption_key, Command::Delete { item: "testname".to_string(), yes: true }).unwrap();
+        let after_delete = fs::read(&file_path).unwrap();
+
+        assert!(!Database::restore_backup(&file_path, 1, "wrongpass").unwrap());
+        assert_eq!(fs::read(&file_path).unwrap(), after_delete);
+
+        assert!(Database::restore_backup(&file_path, 1, "testpass").unwrap());
+        assert_eq!(fs::read(&file_path).unwrap(), before_delete);
+        // The database that was replaced can itself be restored
+        assert_eq!(fs::read(backup::backup_path(&file_path, 1)).unwrap(), after_delete);
+
+        assert!(matches!(Database::restore_backup(&file_path, 10, "testpass"), Err(DatabaseError::CommandError(_))));
+    }
+
+    #[test]
+    fn restore_backup_rejects_corrupt_entries() {
+        let (_dir, file_path, database, encryption_key) = unlocked_test_database();
+        database.set_backups(file_path.clone(), &encryption_key, Command::KeepBackups(backup::DEFAULT_KEEP)).unwrap();
+
+        // Damage a password in the newest backup, the key slot still opens
+        let backup_path = backup::backup_path(&file_path, 1);
+        let mut backup = Database::load(&backup_path).unwrap();
+        backup.passwords[0].password = backup.passwords[0].name.clone();
+        fs::write(&backup_path, format::encode(&backup.header, serde_json::json!(&backup).to_string().as_bytes()).unwrap()).unwrap();
+
+        let current = fs::read(&file_path).unwrap();
+        assert!(Database::restore_backup(&file_path, 1, "testpass").is_err());
+        assert_eq!(fs::read(&file_path).unwrap(), current);
+    }
+
+    #[test]
+    fn find_password_by_id_prefix_or_name() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let cmd = Command::New { name: Some("othername".to_string()), kind: EntryKind::Login, user: None, pass: None, generate: None, fields: FieldOptions::default() };
+        database.new_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let id = database.passwords[1].id;
+
+        assert_eq!(database.find_password(&encryption_key, &id.to_string()).unwrap(), 1);
+        assert_eq!(database.find_password(&encryption_key, &id.to_string().to_uppercase()).unwrap(), 1);
+        assert_eq!(database.find_password(&encryption_key, &database.passwords[1].short_id()).unwrap(), 1);
+        assert_eq!(database.find_password(&encryption_key, "othername").unwrap(), 1);
+        assert_eq!(database.find_password(&encryption_key, "testname").unwrap(), 0);
+        assert!(database.find_password(&encryption_key, "missing").is_err());
+
+        // A name wins over another entry's id starting with it
+        let name = database.passwords[1].short_id()[..MIN_PREFIX_LEN].to_string();
+        let cmd = Command::New { name: Some(name.clone()), kind: EntryKind::Login, user: None, pass: None, generate: None, fields: FieldOptions::default() };
+        database.new_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        assert_eq!(database.find_password(&encryption_key, &name).unwrap(), 2);
+
+        // Ids stay the same when an earlier password is deleted
+        database.del_password(file_path.clone(), &encryption_key, Command::Delete { item: "testname".to_string(), yes: true }).unwrap();
+        assert_eq!(database.find_password(&encryption_key, &id.to_string()).unwrap(), 0);
+    }
+
+    #[test]
+    fn find_password_ambiguous_name() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let cmd = Command::New { name: Some("testname".to_string()), kind: EntryKind::Login, user: None, pass: None, generate: None, fields: FieldOptions::default() };
+        database.new_password(file_path.clone(), &encryption_key, cmd).unwrap();
+
+        assert!(matches!(database.find_password(&encryption_key, "testname"), Err(DatabaseError::AmbiguousMatch(_, candidates)) if candidates.len() == 2));
+        let id = database.passwords[1].id.to_string();
+        assert_eq!(database.find_password(&encryption_key, &id).unwrap(), 1);
+    }
+
+    #[test]
+    fn find_password_closest_match() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        for name in ["github", "github enterprise", "gitlab"] {
+            let cmd = Command::New { name: Some(name.to_string()), kind: EntryKind::Login, user: Some("me".to_string()), pass: None, generate: None, fields: FieldOptions::default() };
+            database.new_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        }
+
+        // An exact name wins over the longer name it is a prefix of
+        assert_eq!(database.find_password(&encryption_key, "GitHub").unwrap(), 1);
+        assert_eq!(database.find_password(&encryption_key, "enterprise").unwrap(), 2);
+        assert_eq!(database.find_password(&encryption_key, "testuser").unwrap(), 0);
+        assert_eq!(database.find_password(&encryption_key, "gitlba").unwrap(), 3);
+        assert!(matches!(database.find_password(&encryption_key, "git"), Err(DatabaseError::AmbiguousMatch(_, candidates)) if candidates.len() == 3));
+        assert!(matches!(database.find_password(&encryption_key, "bitbucket"), Err(DatabaseError::CommandError(_))));
+    }
+
+    #[test]
+    fn edit_and_get_update_timestamps() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let mut encrypted = database.passwords[0].decrypt(&encryption_key).unwrap();
+        encrypted.created = Some("2020-01-01T00:00:00Z".to_string());
+        encrypted.modified = encrypted.created.clone();
+        encrypted.password_changed = encrypted.created.clone();
+        database.passwords[0] = encrypted.encrypt(&encryption_key).unwrap();
+
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: Some("newuser".to_string()), pass: None, ask_pass: false, generate: None, fields: FieldOptions::default() };
+        database.edit_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.created.as_deref(), Some("2020-01-01T00:00:00Z"));
+        assert_eq!(password.password_changed, password.created);
+        assert_ne!(password.modified, password.created);
+        assert!(password.last_accessed.is_none());
+
+        // Recording a use doesn't take a backup
+        let backups = backup::list(&file_path).unwrap().len();
+        database.record_access(file_path.clone(), &encryption_key, password.id).unwrap();
+        assert_eq!(backup::list(&file_path).unwrap().len(), backups);
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert!(password.last_accessed.is_some());
+    }
+
+    #[test]
+    fn edit_custom_fields() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let fields = FieldOptions {
+            notes: Some("testnotes".to_string()),
+            add_urls: vec!["https://example.com".to_string()],
+            set_fields: vec![CustomField { name: "answer".to_string(), kind: FieldKind::Hidden, value: "blue".to_string() }],
+            ..FieldOptions::default()
+        };
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: None, pass: None, ask_pass: false, generate: None, fields };
+        database.edit_password(file_path.clone(), &encryption_key, cmd).unwrap();
+
+        let fields = FieldOptions { notes: Some(String::new()), remove_fields: vec!["answer".to_string()], ..FieldOptions::default() };
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: None, pass: None, ask_pass: false, generate: None, fields };
+        database.edit_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.notes, None);
+        assert_eq!(password.urls, vec!["https://example.com"]);
+        assert!(password.fields.is_empty());
+
+        // Removing a field that isn't there is an error, so a mistyped name isn't missed
+        let fields = FieldOptions { remove_fields: vec!["answer".to_string()], ..FieldOptions::default() };
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: None, pass: None, ask_pass: false, generate: None, fields };
+        assert!(matches!(database.edit_password(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+    }
+
+    #[test]
+    fn hotp_counter_is_saved() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let cmd = Command::Otp("testname".to_string());
+        assert!(matches!(database.otp_code(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+
+        // The RFC 4226 test secret
+        let fields = FieldOptions { otp: Some("otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0".to_string()), ..FieldOptions::default() };
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: None, pass: None, ask_pass: false, generate: None, fields };
+        database.edit_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        for code in ["755224", "287082"] {
+            let mut database = Database::load(&file_path).unwrap();
+            let encryption_key = database.unlock("testpass").unwrap().unwrap();
+            assert_eq!(database.otp_code(file_path.clone(), &encryption_key, Command::Otp("testname".to_string())).unwrap().code, code);
+        }
+    }
+
+    #[test]
+    fn restore_previous_password() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let cmd = Command::Edit { item: "testname".to_string(), name: None, user: None, pass: Some("rejected".to_string()), ask_pass: true, generate: None, fields: FieldOptions::default() };
+        database.edit_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let password = database.get_history(&encryption_key, Command::History("testname".to_string())).unwrap();
+        assert_eq!(password.history.len(), 1);
+        assert_eq!(password.history[0].password, "testpass");
+
+        let cmd = Command::Restore { item: "testname".to_string(), number: 2 };
+        assert!(matches!(database.restore_password(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+        let cmd = Command::Restore { item: "testname".to_string(), number: 1 };
+        database.restore_password(file_path.clone(), &encryption_key, cmd).unwrap();
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.password, "testpass");
+        assert_eq!(password.history[0].password, "rejected");
+    }
+
+    #[test]
+    fn move_and_tag_passwords() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let cmd = Command::New { name: Some("othername".to_string()), kind: EntryKind::Login, user: None, pass: None, generate: None, fields: FieldOptions::default() };
+        database.new_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let cmd = Command::Move { item: "testname".to_string(), folder: Some("prod/aws".to_string()) };
+        database.move_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let cmd = Command::Tag { item: "testname".to_string(), add: vec!["AWS".to_string(), "root".to_string()], remove: vec![] };
+        database.tag_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let cmd = Command::Tag { item: "testname".to_string(), add: vec!["aws".to_string()], remove: vec!["ROOT".to_string()] };
+        database.tag_password(file_path.clone(), &encryption_key, cmd).unwrap();
+        let cmd = Command::Tag { item: "testname".to_string(), add: vec![], remove: vec!["root".to_string()] };
+        assert!(matches!(database.tag_password(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.folder.as_deref(), Some("prod/aws"));
+        assert_eq!(password.tags, vec!["AWS"]);
+
+        let names = |options: &ListOptions| -> Vec<String> {
+            database.select_passwords(&encryption_key, options, Utc::now()).unwrap().into_iter().map(|password| password.name).collect()
+        };
+        assert_eq!(names(&ListOptions { folder: Some(Some("prod".to_string())), ..ListOptions::default() }), vec!["testname"]);
+        assert_eq!(names(&ListOptions { folder: Some(None), ..ListOptions::default() }), vec!["othername"]);
+        assert_eq!(names(&ListOptions { tags: vec!["aws".to_string()], ..ListOptions::default() }), vec!["testname"]);
+        assert!(names(&ListOptions { tags: vec!["aws".to_string(), "root".to_string()], ..ListOptions::default() }).is_empty());
+        assert_eq!(names(&ListOptions::default()).len(), 2);
+    }
+
+    #[test]
+    fn card_details() {
+        let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let new_card = |fields| Command::New { name: Some("visa".to_string()), kind: EntryKind::Card, user: None, pass: None, generate: None, fields };
+
+        // A card needs a number, which must pass the Luhn check and can't be an argument
+        let fields = FieldOptions { set_details: vec![("expiry".to_string(), "3/30".to_string())], ..FieldOptions::default() };
+        assert!(matches!(database.new_password(file_path.clone(), &encryption_key, new_card(fields)), Err(DatabaseError::CommandError(_))));
+        let fields = FieldOptions { secret_details: vec![("number".to_string(), Some("4111 1111 1111 1112".to_string()))], ..FieldOptions::default() };
+        assert!(matches!(database.new_password(file_path.clone(), &encryption_key, new_card(fields)), Err(DatabaseError::CommandError(_))));
+        let fields = FieldOptions { set_details: vec![("number".to_string(), "4111111111111111".to_string())], ..FieldOptions::default() };
+        assert!(matches!(database.new_password(file_path.clone(), &encryption_key, new_card(fields)), Err(DatabaseError::CommandError(_))));
+        assert_eq!(database.passwords.len(), 1);
+
+        let fields = FieldOptions {
+            set_details: vec![("expiry".to_string(), "3/30".to_string())],
+            secret_details: vec![("number".to_string(), Some("4111 1111 1111 1111".to_string()))],
+            ..FieldOptions::default()
+        };
+        database.new_password(file_path.clone(), &encryption_key, new_card(fields)).unwrap();
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_password(&encryption_key, Command::Get { item: "visa".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.kind, EntryKind::Card);
+        assert_eq!(password.details["number"], "4111111111111111");
+        assert_eq!(password.details["expiry"], "03/30");
+
+        // The number can be changed but not removed
+        let fields = FieldOptions { remove_details: vec!["number".to_string()], ..FieldOptions::default() };
+        let cmd = Command::Edit { item: "visa".to_string(), name: None, user: None, pass: None, ask_pass: false, generate: None, fields };
+        assert!(matches!(database.edit_password(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+
+        let names = |kind| -> Vec<String> {
+            let options = ListOptions { kind: Some(kind), ..ListOptions::default() };
+            database.select_passwords(&encryption_key, &options, Utc::now()).unwrap().into_iter().map(|password| password.name).collect()
+        };
+        assert_eq!(names(EntryKind::Card), vec!["visa"]);
+        assert_eq!(names(EntryKind::Login), vec!["testname"]);
+        assert!(names(EntryKind::Wifi).is_empty());
+        assert_eq!(database.audit_passwords(&encryption_key, 365, None).unwrap().entries, 1);
+    }
+
+    #[test]
+    fn attach_extract_and_detach() {
+        let (dir, file_path, mut database, encryption_key) = unlocked_test_database();
+        let file = dir.path().join("codes.txt");
+        fs::write(&file, "1234-5678\n").unwrap();
+        let file = file.to_str().unwrap().to_string();
+        let cmd = Command::Attach { item: "testname".to_string(), file: file.clone(), name: None };
+        let attached = database.attach_file(file_path.clone(), &encryption_key, cmd).unwrap();
+        assert_eq!((attached.name.as_str(), attached.size), ("codes.txt", 10));
+        let cmd = Command::Attach { item: "testname".to_string(), file: file.clone(), name: None };
+        assert!(matches!(database.attach_file(file_path.clone(), &encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+        let cmd = Command::Attach { item: "testname".to_string(), file, name: Some("copy".to_string()) };
+        database.attach_file(file_path.clone(), &encryption_key, cmd).unwrap();
+
+        // Only the id and size are readable in the saved database
+        assert!(!fs::read_to_string(&file_path).unwrap().contains("codes.txt"));
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        let password = database.get_attachments(&encryption_key, Command::Attachments("testname".to_string())).unwrap();
+        assert_eq!(password.attachments.len(), 2);
+
+        let cmd = Command::Extract { item: "testname".to_string(), name: "codes.txt".to_string(), output: None };
+        let attachment = database.get_attachment(&encryption_key, cmd).unwrap();
+        let mut contents = vec![];
+        attachment::extract(&file_path, &encryption_key, &attachment.id, &mut contents).unwrap();
+        assert_eq!(contents, b"1234-5678\n");
+        let cmd = Command::Extract { item: "testname".to_string(), name: "missing".to_string(), output: None };
+        assert!(matches!(database.get_attachment(&encryption_key, cmd), Err(DatabaseError::CommandError(_))));
+
+        let cmd = Command::Detach { item: "testname".to_string(), name: "codes.txt".to_string() };
+        database.detach_file(file_path.clone(), &encryption_key, cmd).unwrap();
+        assert!(!attachment::path(&file_path, &attachment.id).exists());
+
+        // Emptying the trash removes the files of the entries in it
+        let copy = database.passwords[0].attachments[0].id;
+        database.del_password(file_path.clone(), &encryption_key, Command::Delete { item: "testname".to_string(), yes: true }).unwrap();
+        assert!(attachment::path(&file_path, &copy).exists());
+        database.empty_trash(file_path.clone(), &encryption_key, Command::EmptyTrash { yes: true }).unwrap();
+        assert!(!attachment::path(&file_path, &copy).exists());
+    }
+
+    #[test]
+    fn delete_to_trash_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.oxd").to_str().unwrap().to_string();
+        create(&file_path, LayoutOptions { layout: Some(Layout::Sealed), padding: Some(0) });
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        database.del_password(file_path.clone(), &encryption_key, Command::Delete { item: "testname".to_string(), yes: true }).unwrap();
+
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        assert!(database.passwords.is_empty());
+        assert!(database.trash[0].decrypt(&encryption_key).unwrap().deleted.is_some());
+        assert!(matches!(database.find_password(&encryption_key, "testname"), Err(DatabaseError::CommandError(_))));
+
+        database.restore_from_trash(file_path.clone(), &encryption_key, Command::RestoreTrash("testname".to_string())).unwrap();
+        let mut database = Database::load(&file_path).unwrap();
+        let encryption_key = database.unlock("testpass").unwrap().unwrap();
+        assert!(database.trash.is_empty());
+        let password = database.get_password(&encryption_key, Command::Get { item: "testname".to_string(), show_hidden: false }).unwrap();
+        assert_eq!(password.password, "testpass");
+        assert!(password.deleted.is_none());
+    }
+
+    #[test]
+    fn purge_trash_after_keep_days() {
+        let mut database = empty_database();
+        let encryption_key = [7u8; 32];
+        for (name, deleted) in [("old", "2021-01-01T00:00:00Z"), ("recent", "2021-01-20T00:00:00Z")] {
+            let mut password = Password::new(name.to_string(), String::new(), String::new());
+            password.deleted = Some(deleted.to_string());
+            database.trash.push(password.encrypt(&encryption_key).unwrap());
+        }
+
+        let now = DateTime::parse_from_rfc3339("2021-02-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        database.purge_trash(&encryption_key, now).unwrap();
+        assert_eq!(database.trash.len(), 1);
+        assert_eq!(database.trash[0].decrypt(&encryption_key).unwrap().name, "recent");
+    }
+
+    #[test]
+    fn select_due_passwords() {
+        let mut database = empty_database();
+        let encryption_key = [7u8; 32];
+        for (name, expires, rotate_days) in [("later", Some("2021-03-01"), None), ("rotated", None, Some(30)), ("expired", Some("2021-01-15"), Some(365)), ("never", None, None)] {
+            let mut password = Password::new(name.to_string(), String::new(), String::new());
+            password.password_changed = Some("2021-01-01T00:00:00Z".to_string());
+            password.expires = expires.map(str::to_string);
+            password.rotate_days = rotate_days.map(|days: u32| days.to_string());
+            database.passwords.push(password.encrypt(&encryption_key).unwrap());
+        }
+
+        let now = DateTime::parse_from_rfc3339("2021-02-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let names = |within| -> Vec<String> {
+            database.select_due(&encryption_key, within, now).unwrap().into_iter().map(|(_, password)| password.name).collect()
+        };
+        assert_eq!(names(chrono::Duration::days(14)), vec!["expired", "rotated"]);
+        assert_eq!(names(chrono::Duration::days(28)), vec!["expired", "rotated", "later"]);
+    }
+
+    #[test]
+    fn select_passwords_older_than_and_sorted() {
+        let mut database = empty_database();
+        let encryption_key = [7u8; 32];
+        for (name, changed) in [("b", Some("2020-06-01T00:00:00Z")), ("a", Some("2020-01-01T00:00:00Z")), ("new", Some("2020-12-01T00:00:00Z")), ("c", None)] {
+            let mut password = Password::new(name.to_string(), String::new(), String::new());
+            password.password_changed = changed.map(str::to_string);
+            database.passwords.push(password.encrypt(&encryption_key).unwrap());
+        }
+        let now = DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let names = |options: &ListOptions| -> Vec<String> {
+            database.select_passwords(&encryption_key, options, now).unwrap().into_iter().map(|password| password.name).collect()
+        };
+
+        assert_eq!(names(&ListOptions::default()), vec!["b", "a", "new", "c"]);
+        let older_than = Some(chrono::Duration::days(180));
+        assert_eq!(names(&ListOptions { older_than, ..ListOptions::default() }), vec!["b", "a", "c"]);
+        assert_eq!(names(&ListOptions { sort: Some(SortKey::Changed), older_than, ..ListOptions::default() }), vec!["c", "a", "b"]);
+        assert_eq!(names(&ListOptions { sort: Some(SortKey::Name), ..ListOptions::default() }), vec!["a", "b", "c", "new"]);
+    }
+
+    #[test]
+    fn failed_save_keeps_old_database() {
+        for step in [atomic::Step::Create, atomic::Step::Permissions, atomic::Step::Write, atomic::Step::Sync, atomic::Step::Rename] {
+            let (_dir, file_path, mut database, encryption_key) = unlocked_test_database();
+            let cmd = Command::New { name: Some("newname".to_string()), kind: EntryKind::Login, user: None, pass: None, generate: None, fields: FieldOptions::default() };
+            atomic::fail_at(Some(step));
+            let result = database.new_password(file_path.clone(), &encryption_key, cmd);
+            atomic::fail_at(None);
+            assert!(result.is_err(), "{step:?} should fail");
+
+            let mut database = Database::load(&file_path).unwrap();
+            assert!(database.unlock("testpass").unwrap().is_some());
+            assert_eq!(database.passwords.len(), 1, "{step:?} changed the database");
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Expiry dates and rotation intervals for passwords that have to be changed on a schedule.
+//! A password is due on its expiry date or once it is older than its rotation interval,
+//! whichever comes first.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use crate::password;
+
+// How far ahead the due command looks for passwords that will soon be due
+pub const DEFAULT_WITHIN_DAYS: u32 = 14;
+// The longest rotation interval that can be set, a hundred years
+pub const MAX_ROTATE_DAYS: u32 = 36500;
+
+/// Expiry dates are whole days, given and saved as `YYYY-MM-DD`
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()
+}
+
+/// When a password has to be changed by, if it has an expiry date or rotation interval.
+/// Passwords saved before changes were recorded are rotated from when they were created, and
+/// are due at once if that isn't known either. An interval that runs past the latest date that
+/// can be represented is never due.
+pub fn due(expires: Option<&str>, rotate_days: Option<u32>, changed: Option<&str>) -> Option<DateTime<Utc>> {
+    let expires = expires.and_then(parse_date).map(|date| date.and_time(Default::default()).and_utc());
+    let rotate = rotate_days.and_then(|days| match changed.and_then(password::parse_timestamp) {
+        Some(changed) => changed.checked_add_signed(Duration::days(days.into())),
+        None => Some(DateTime::UNIX_EPOCH),
+    });
+    match (expires, rotate) {
+        (Some(expires), Some(rotate)) => Some(expires.min(rotate)),
+        (expires, rotate) => expires.or(rotate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_dates() {
+        let date = |timestamp: &str| DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc);
+        assert_eq!(due(None, None, Some("2021-01-01T00:00:00Z")), None);
+        assert_eq!(due(Some("2021-03-01"), None, None), Some(date("2021-03-01T00:00:00Z")));
+        assert_eq!(due(None, Some(90), Some("2021-01-01T12:00:00Z")), Some(date("2021-04-01T12:00:00Z")));
+        assert_eq!(due(Some("2021-03-01"), Some(90), Some("2021-01-01T00:00:00Z")), Some(date("2021-03-01T00:00:00Z")));
+        assert_eq!(due(None, Some(90), None), Some(DateTime::UNIX_EPOCH));
+        assert_eq!(due(None, Some(u32::MAX), Some("2021-01-01T00:00:00Z")), None);
+        assert!(parse_date("2021-02-30").is_none());
+    }
+}
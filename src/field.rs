@@ -0,0 +1,117 @@
+//! Custom fields saved alongside a password, each with a kind that decides how it is checked
+//! and shown.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    #[default]
+    Text,
+    // Secrets like security question answers, only shown by `get --show-hidden`
+    Hidden,
+    Url,
+    Email,
+}
+
+impl FieldKind {
+    pub fn parse(kind: &str) -> Option<FieldKind> {
+        match kind {
+            "text" => Some(FieldKind::Text),
+            "hidden" => Some(FieldKind::Hidden),
+            "url" => Some(FieldKind::Url),
+            "email" => Some(FieldKind::Email),
+            _ => None,
+        }
+    }
+
+    // Values are encrypted with their kind in the associated data, so a hidden field can't be
+    // turned into a text field without the key
+    pub fn value_field(self) -> &'static str {
+        match self {
+            FieldKind::Text => "fields.text",
+            FieldKind::Hidden => "fields.hidden",
+            FieldKind::Url => "fields.url",
+            FieldKind::Email => "fields.email",
+        }
+    }
+}
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FieldKind::Text => "text",
+            FieldKind::Hidden => "hidden",
+            FieldKind::Url => "url",
+            FieldKind::Email => "email",
+        })
+    }
+}
+
+/// Check a value is valid for its kind, returning it as it should be saved
+pub fn normalize(kind: FieldKind, value: String) -> Result<String, FieldError> {
+    match kind {
+        FieldKind::Text | FieldKind::Hidden => Ok(value),
+        FieldKind::Url => normalize_url(value),
+        FieldKind::Email => {
+            let valid = value.split_once('@').is_some_and(|(local, domain)| {
+                !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') && !domain.contains('@')
+            });
+            if valid && !value.contains(char::is_whitespace) {
+                Ok(value)
+            } else {
+                Err(FieldError::InvalidEmail(value))
+            }
+        },
+    }
+}
+
+/// URLs given without a scheme, like `example.com/login`, are taken to be https
+pub fn normalize_url(url: String) -> Result<String, FieldError> {
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return Err(FieldError::InvalidUrl(url));
+    }
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+            if valid_scheme && !rest.is_empty() { Ok(url) } else { Err(FieldError::InvalidUrl(url)) }
+        },
+        None => Ok(format!("https://{url}")),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FieldError {
+    #[error("\"{0}\" is not a valid URL")]
+    InvalidUrl(String),
+    #[error("\"{0}\" is not a valid email address")]
+    InvalidEmail(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert_eq!(normalize_url("https://example.com/login".to_string()).unwrap(), "https://example.com/login");
+        assert_eq!(normalize_url("example.com".to_string()).unwrap(), "https://example.com");
+        assert_eq!(normalize_url("ssh+git://host".to_string()).unwrap(), "ssh+git://host");
+        for url in ["", "https://", "1a://host", "example .com"] {
+            assert!(matches!(normalize_url(url.to_string()), Err(FieldError::InvalidUrl(_))), "{url} was accepted");
+        }
+    }
+
+    #[test]
+    fn emails() {
+        assert!(normalize(FieldKind::Email, "me@example.com".to_string()).is_ok());
+        for email in ["me", "@example.com", "me@example", "me@.com", "me@example.com.", "me@a@b.com", "m e@example.com"] {
+            assert!(matches!(normalize(FieldKind::Email, email.to_string()), Err(FieldError::InvalidEmail(_))), "{email} was accepted");
+        }
+        assert_eq!(normalize(FieldKind::Text, "anything at all".to_string()).unwrap(), "anything at all");
+    }
+}
@@ -0,0 +1,107 @@
+//! Folders and tags for organizing passwords. A folder is a path like `prod/aws`, each
+//! password is in at most one, and tags are single words that can be shared by any number
+//! of passwords.
+
+use thiserror::Error;
+
+/// Tidy a folder path, dropping empty parts and surrounding spaces so `/prod//aws/` is
+/// `prod/aws`. The top level, with no folder, is `None`.
+pub fn normalize_folder(path: &str) -> Option<String> {
+    let parts: Vec<&str> = path.split('/').map(str::trim).filter(|part| !part.is_empty()).collect();
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Whether a password in `folder` is in `filter` or one of its subfolders
+pub fn in_folder(folder: Option<&str>, filter: &str) -> bool {
+    folder.is_some_and(|folder| {
+        folder.eq_ignore_ascii_case(filter)
+            || (folder.get(..filter.len()).is_some_and(|start| start.eq_ignore_ascii_case(filter)) && folder[filter.len()..].starts_with('/'))
+    })
+}
+
+pub fn normalize_tag(tag: &str) -> Result<String, FolderError> {
+    let tag = tag.trim().trim_start_matches('#');
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(FolderError::InvalidTag(tag.to_string()));
+    }
+    Ok(tag.to_string())
+}
+
+// Tags are matched ignoring case, so `AWS` and `aws` are the same tag
+pub fn has_tag(tags: &[String], tag: &str) -> bool {
+    tags.iter().any(|saved| saved.eq_ignore_ascii_case(tag))
+}
+
+/// Draw lines grouped under their folders, each folder indented under its parent. Lines keep
+/// their order within a folder, and lines with no folder come first. Folders are grouped
+/// ignoring case, like they are matched, and shown as they are spelled in their first line.
+pub fn render_tree(mut lines: Vec<(Option<String>, String)>) -> String {
+    let path = |folder: &Option<String>| -> Vec<String> {
+        folder.as_deref().map(|folder| folder.split('/').map(str::to_string).collect()).unwrap_or_default()
+    };
+    lines.sort_by_key(|(folder, _)| path(&folder.as_deref().map(str::to_ascii_lowercase)));
+
+    let mut tree = String::new();
+    let mut previous: Vec<String> = vec![];
+    for (folder, line) in &lines {
+        let parts = path(folder);
+        let shared = parts.iter().zip(&previous).take_while(|(part, previous)| part.eq_ignore_ascii_case(previous)).count();
+        for (depth, part) in parts.iter().enumerate().skip(shared) {
+            tree.push_str(&format!("{}{part}/\n", "  ".repeat(depth)));
+        }
+        tree.push_str(&format!("{}{line}\n", "  ".repeat(parts.len())));
+        previous = parts;
+    }
+    tree
+}
+
+#[derive(Error, Debug)]
+pub enum FolderError {
+    #[error("\"{0}\" is not a tag, tags are single words without commas")]
+    InvalidTag(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders() {
+        assert_eq!(normalize_folder(" /prod// aws /").as_deref(), Some("prod/aws"));
+        assert_eq!(normalize_folder("/"), None);
+        assert!(in_folder(Some("prod/aws"), "prod"));
+        assert!(in_folder(Some("Prod/aws"), "prod/AWS"));
+        assert!(!in_folder(Some("production"), "prod"));
+        assert!(!in_folder(None, "prod"));
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(normalize_tag(" #aws").unwrap(), "aws");
+        assert!(matches!(normalize_tag("two words"), Err(FolderError::InvalidTag(_))));
+        assert!(matches!(normalize_tag("a,b"), Err(FolderError::InvalidTag(_))));
+        assert!(has_tag(&["AWS".to_string()], "aws"));
+    }
+
+    #[test]
+    fn tree() {
+        let lines = vec![
+            (Some("staging/db".to_string()), "postgres".to_string()),
+            (Some("prod/aws".to_string()), "console".to_string()),
+            (None, "email".to_string()),
+            (Some("prod".to_string()), "vpn".to_string()),
+            (Some("Prod/aws".to_string()), "root".to_string()),
+        ];
+        assert_eq!(render_tree(lines), "\
+email
+prod/
+  vpn
+  aws/
+    console
+    root
+staging/
+  db/
+    postgres
+");
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{backup, cipher::Cipher, kdf::KdfParams, keyslot::KeySlot, trash};
+
+pub const MAGIC: &[u8; 4] = b"OXPW";
+pub const FORMAT_VERSION: u16 = 5;
+
+// Magic bytes, then a big-endian u16 format version and u32 header length
+const PREAMBLE_LEN: usize = MAGIC.len() + 2 + 4;
+
+/// Describes how the rest of the file is protected. It is stored in the clear ahead of
+/// the body so a database can be unlocked without guessing at its layout.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    pub key_slots: Vec<KeySlot>,
+    pub cipher: Cipher,
+    #[serde(default)]
+    pub layout: Layout,
+    // Sealed bodies are padded up to a multiple of this many bytes, 0 disables padding
+    #[serde(default)]
+    pub padding: u32,
+    // How many previous copies of the file to keep each time it is saved
+    #[serde(default = "default_backups")]
+    pub backups: u32,
+    // How many days deleted passwords stay in the trash
+    #[serde(default = "default_trash_days")]
+    pub trash_days: u32,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            key_slots: vec![],
+            cipher: Cipher::default(),
+            layout: Layout::default(),
+            padding: 0,
+            backups: default_backups(),
+            trash_days: default_trash_days(),
+        }
+    }
+}
+
+fn default_backups() -> u32 {
+    backup::DEFAULT_KEEP
+}
+
+fn default_trash_days() -> u32 {
+    trash::DEFAULT_KEEP_DAYS
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Layout {
+    // The body is plain JSON with each password field encrypted on its own
+    #[default]
+    #[serde(rename = "entries")]
+    Entries,
+    // The whole body is encrypted as a single blob, so only the header is readable
+    #[serde(rename = "sealed")]
+    Sealed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct KdfHeader {
+    pub id: KdfId,
+    #[serde(flatten)]
+    pub params: KdfParams,
+    pub salt: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum KdfId {
+    #[default]
+    #[serde(rename = "argon2id")]
+    Argon2id,
+}
+
+pub fn encode(header: &Header, body: &[u8]) -> Result<Vec<u8>, FormatError> {
+    Ok(frame(FORMAT_VERSION, &serde_json::to_vec(header)?, body))
+}
+
+/// Pad a body with trailing whitespace up to a multiple of `padding` bytes, which is still
+/// valid JSON so no extra framing is needed to strip it
+pub fn pad(body: &mut Vec<u8>, padding: u32) {
+    if padding > 0 {
+        let bucket = padding as usize;
+        body.resize(body.len().div_ceil(bucket) * bucket, b' ');
+    }
+}
+
+/// Split a current format file into its header and body
+pub fn decode(contents: &[u8]) -> Result<(Header, &[u8]), FormatError> {
+    let (version, header, body) = split(contents)?;
+    if version != FORMAT_VERSION {
+        return Err(FormatError::OutdatedVersion(version));
+    }
+
+    Ok((serde_json::from_slice(header)?, body))
+}
+
+/// Lay out a file of any framed format version from its serialized header and body
+pub fn frame(version: u16, header: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(PREAMBLE_LEN + header.len() + body.len());
+    contents.extend_from_slice(MAGIC);
+    contents.extend_from_slice(&version.to_be_bytes());
+    contents.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    contents.extend_from_slice(header);
+    contents.extend_from_slice(body);
+    contents
+}
+
+/// Split a framed file into its version, serialized header and body
+pub fn split(contents: &[u8]) -> Result<(u16, &[u8], &[u8]), FormatError> {
+    let version = detect_version(contents)?;
+    if !contents.starts_with(MAGIC) {
+        return Err(FormatError::OutdatedVersion(version));
+    }
+
+    let header_len = u32::from_be_bytes(contents[MAGIC.len() + 2..PREAMBLE_LEN].try_into().unwrap()) as usize;
+    if contents.len() < PREAMBLE_LEN + header_len {
+        return Err(FormatError::Truncated);
+    }
+    let (header, body) = contents[PREAMBLE_LEN..].split_at(header_len);
+
+    Ok((version, header, body))
+}
+
+/// Work out which format version a file was written with. Files from before the header was
+/// added are bare JSON, their version is inferred from which fields are present.
+pub fn detect_version(contents: &[u8]) -> Result<u16, FormatError> {
+    if contents.starts_with(MAGIC) {
+        if contents.len() < PREAMBLE_LEN {
+            return Err(FormatError::Truncated);
+        }
+        let version = u16::from_be_bytes([contents[MAGIC.len()], contents[MAGIC.len() + 1]]);
+        if version > FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        return Ok(version);
+    }
+
+    let fields: serde_json::Map<String, serde_json::Value> = match serde_json::from_slice(contents) {
+        Ok(fields) => fields,
+        Err(_) => return Err(FormatError::UnknownFormat),
+    };
+    if fields.contains_key("cipher") {
+        Ok(2)
+    } else if fields.contains_key("kdf") {
+        Ok(1)
+    } else if fields.contains_key("master_password") {
+        Ok(0)
+    } else {
+        Err(FormatError::UnknownFormat)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("file is not an oxidizepw database")]
+    UnknownFormat,
+    #[error("database format version {0} is newer than this version of oxidizepw supports")]
+    UnsupportedVersion(u16),
+    #[error("database format version {0} needs to be migrated before it can be opened")]
+    OutdatedVersion(u16),
+    #[error("database file is truncated")]
+    Truncated,
+    #[error("database header is invalid")]
+    InvalidHeader(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let header = Header {
+            key_slots: vec![KeySlot {
+                kind: crate::keyslot::KeySlotKind::Password,
+                kdf: KdfHeader { salt: vec![1, 2, 3], ..Default::default() },
+                wrapped_key: vec![4, 5, 6],
+            }],
+            layout: Layout::Sealed,
+            padding: 1024,
+            ..Default::default()
+        };
+        let contents = encode(&header, b"body").unwrap();
+        assert!(contents.starts_with(MAGIC));
+        assert_eq!(detect_version(&contents).unwrap(), FORMAT_VERSION);
+
+        let (decoded_header, body) = decode(&contents).unwrap();
+        assert_eq!(decoded_header, header);
+        assert_eq!(body, b"body");
+    }
+
+    #[test]
+    fn pad_to_bucket() {
+        let mut body = b"{}".to_vec();
+        pad(&mut body, 0);
+        assert_eq!(body, b"{}");
+        pad(&mut body, 16);
+        assert_eq!(body.len(), 16);
+        pad(&mut body, 16);
+        assert_eq!(body.len(), 16);
+        assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+    }
+
+    #[test]
+    fn detect_legacy_versions() {
+        assert_eq!(detect_version(br#"{"master_password":[],"passwords":[]}"#).unwrap(), 0);
+        assert_eq!(detect_version(br#"{"kdf":{},"master_password":[],"passwords":[]}"#).unwrap(), 1);
+        assert_eq!(detect_version(br#"{"cipher":"xchacha20poly1305","kdf":{},"master_password":[],"passwords":[]}"#).unwrap(), 2);
+    }
+
+    #[test]
+    fn detect_rejects_unknown() {
+        assert!(matches!(detect_version(b"not a database"), Err(FormatError::UnknownFormat)));
+        assert!(matches!(detect_version(b"OXPW"), Err(FormatError::Truncated)));
+
+        let mut contents = encode(&Header::default(), b"").unwrap();
+        contents[MAGIC.len() + 1] = 0xff;
+        assert!(matches!(detect_version(&contents), Err(FormatError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn decode_rejects_truncated() {
+        let contents = encode(&Header::default(), b"").unwrap();
+        assert!(matches!(decode(&contents[..contents.len() - 1]), Err(FormatError::Truncated)));
+    }
+}
@@ -0,0 +1,163 @@
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use thiserror::Error;
+
+pub const DEFAULT_LENGTH: usize = 20;
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+// Characters that are easily confused with each other when read or typed by hand
+const AMBIGUOUS: &str = "0O1lI|`'\"";
+
+/// What a generated password has to look like. Every class that is included appears at
+/// least once, sites that restrict passwords can be matched with `max_length` and `symbols`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub length: usize,
+    pub lower: bool,
+    pub upper: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    // Replaces the full set of symbols, for sites that only accept some of them
+    pub allowed_symbols: Option<String>,
+    pub avoid_ambiguous: bool,
+    pub max_length: Option<usize>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            length: DEFAULT_LENGTH,
+            lower: true,
+            upper: true,
+            digits: true,
+            symbols: true,
+            allowed_symbols: None,
+            avoid_ambiguous: false,
+            max_length: None,
+        }
+    }
+}
+
+impl Policy {
+    // The character set of each included class, with ambiguous characters taken out
+    fn classes(&self) -> Result<Vec<Vec<char>>, GeneratorError> {
+        let symbols = self.allowed_symbols.as_deref().unwrap_or(SYMBOLS);
+        let mut classes = vec![];
+        for (included, name, characters) in [
+            (self.lower, "lowercase letters", LOWER),
+            (self.upper, "uppercase letters", UPPER),
+            (self.digits, "digits", DIGITS),
+            (self.symbols, "symbols", symbols),
+        ] {
+            if !included {
+                continue;
+            }
+            let mut class: Vec<char> = characters
+                .chars()
+                .filter(|c| !(self.avoid_ambiguous && AMBIGUOUS.contains(*c)))
+                .collect();
+            class.sort_unstable();
+            class.dedup();
+            if class.is_empty() {
+                return Err(GeneratorError::EmptyClass(name));
+            }
+            classes.push(class);
+        }
+
+        if classes.is_empty() {
+            return Err(GeneratorError::NoClasses);
+        }
+        Ok(classes)
+    }
+}
+
+/// Generate a password from the operating system's random number generator
+pub fn generate(policy: &Policy) -> Result<String, GeneratorError> {
+    let classes = policy.classes()?;
+    let length = policy.max_length.map_or(policy.length, |max_length| policy.length.min(max_length));
+    if length < classes.len() {
+        return Err(GeneratorError::TooShort(length, classes.len()));
+    }
+
+    // One character from each class so none are missing, the rest from all of them
+    let all: Vec<char> = classes.concat();
+    let mut password: Vec<char> = classes.iter().map(|class| pick(class)).collect();
+    password.extend((password.len()..length).map(|_| pick(&all)));
+    password.shuffle(&mut OsRng);
+
+    Ok(password.into_iter().collect())
+}
+
+fn pick(characters: &[char]) -> char {
+    characters[OsRng.gen_range(0..characters.len())]
+}
+
+#[derive(Error, Debug)]
+pub enum GeneratorError {
+    #[error("no character classes are left to generate a password from")]
+    NoClasses,
+    #[error("no {0} are left to generate a password from")]
+    EmptyClass(&'static str),
+    #[error("a {0} character password can't hold one of each of the {1} character classes")]
+    TooShort(usize, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains_any(password: &str, characters: &str) -> bool {
+        password.chars().any(|c| characters.contains(c))
+    }
+
+    #[test]
+    fn generate_default() {
+        for _ in 0..50 {
+            let password = generate(&Policy::default()).unwrap();
+            assert_eq!(password.chars().count(), DEFAULT_LENGTH);
+            for class in [LOWER, UPPER, DIGITS, SYMBOLS] {
+                assert!(contains_any(&password, class), "{password} is missing one of {class}");
+            }
+        }
+    }
+
+    #[test]
+    fn generate_excluded_classes() {
+        let policy = Policy { length: 30, symbols: false, upper: false, ..Policy::default() };
+        for _ in 0..50 {
+            let password = generate(&policy).unwrap();
+            assert!(!contains_any(&password, SYMBOLS));
+            assert!(!contains_any(&password, UPPER));
+        }
+    }
+
+    #[test]
+    fn generate_avoid_ambiguous() {
+        let policy = Policy { length: 100, avoid_ambiguous: true, ..Policy::default() };
+        for _ in 0..20 {
+            assert!(!contains_any(&generate(&policy).unwrap(), AMBIGUOUS));
+        }
+    }
+
+    #[test]
+    fn generate_site_policy() {
+        let policy = Policy { length: 40, allowed_symbols: Some("-_".to_string()), max_length: Some(16), ..Policy::default() };
+        for _ in 0..50 {
+            let password = generate(&policy).unwrap();
+            assert_eq!(password.len(), 16);
+            assert!(contains_any(&password, "-_"));
+            assert!(password.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        }
+    }
+
+    #[test]
+    fn generate_impossible_policies() {
+        assert!(matches!(generate(&Policy { length: 3, ..Policy::default() }), Err(GeneratorError::TooShort(3, 4))));
+        let policy = Policy { lower: false, upper: false, digits: false, symbols: false, ..Policy::default() };
+        assert!(matches!(generate(&policy), Err(GeneratorError::NoClasses)));
+        let policy = Policy { allowed_symbols: Some("|`".to_string()), avoid_ambiguous: true, ..Policy::default() };
+        assert!(matches!(generate(&policy), Err(GeneratorError::EmptyClass("symbols"))));
+    }
+}
@@ -0,0 +1,108 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const SALT_LEN: usize = 16;
+pub const KEY_LEN: usize = 32;
+
+/// Argon2id cost parameters, stored alongside the salt in the database file so they
+/// can be raised later without breaking existing vaults
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// The output of running the master password through the KDF. The verifier is stored
+/// in the database to check the master password, the encryption key never touches disk.
+pub struct MasterKey {
+    pub verifier: Vec<u8>,
+    pub encryption_key: Vec<u8>,
+}
+
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+pub fn derive(password: &str, salt: &[u8], params: &KdfParams) -> Result<MasterKey, KdfError> {
+    let argon2_params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(KEY_LEN * 2))?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params);
+
+    // Derive enough material for two independent keys, one half verifies the password
+    // and the other half encrypts the passwords
+    let mut output = vec![0u8; KEY_LEN * 2];
+    argon2.hash_password_into(password.as_bytes(), salt, &mut output)?;
+    let encryption_key = output.split_off(KEY_LEN);
+
+    Ok(MasterKey {
+        verifier: output,
+        encryption_key,
+    })
+}
+
+/// Compare two byte strings without bailing out at the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Error, Debug)]
+pub enum KdfError {
+    #[error("invalid key derivation parameters: {0}")]
+    InvalidParams(#[from] argon2::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    #[test]
+    fn derive_is_deterministic() {
+        let salt = [7u8; SALT_LEN];
+        let first = derive("testpass", &salt, &TEST_PARAMS).unwrap();
+        let second = derive("testpass", &salt, &TEST_PARAMS).unwrap();
+        assert_eq!(first.verifier, second.verifier);
+        assert_eq!(first.encryption_key, second.encryption_key);
+        assert_ne!(first.verifier, first.encryption_key);
+    }
+
+    #[test]
+    fn derive_depends_on_salt() {
+        let first = derive("testpass", &[1u8; SALT_LEN], &TEST_PARAMS).unwrap();
+        let second = derive("testpass", &[2u8; SALT_LEN], &TEST_PARAMS).unwrap();
+        assert_ne!(first.encryption_key, second.encryption_key);
+    }
+
+    #[test]
+    fn derive_depends_on_params() {
+        let salt = [7u8; SALT_LEN];
+        let stronger = KdfParams { iterations: 2, ..TEST_PARAMS };
+        let first = derive("testpass", &salt, &TEST_PARAMS).unwrap();
+        let second = derive("testpass", &salt, &stronger).unwrap();
+        assert_ne!(first.encryption_key, second.encryption_key);
+    }
+
+    #[test]
+    fn derive_rejects_bad_params() {
+        let params = KdfParams { memory_kib: 1, ..TEST_PARAMS };
+        assert!(derive("testpass", &[7u8; SALT_LEN], &params).is_err());
+    }
+}
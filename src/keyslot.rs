@@ -0,0 +1,98 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    cipher::{self, CipherError},
+    format::{KdfHeader, KdfId},
+    kdf::{self, KdfError, KdfParams, KEY_LEN},
+};
+
+// Binds a wrapped key to the kind of slot it was wrapped for
+pub const PASSWORD_SLOT_AD: &[u8] = b"oxidizepw keyslot password";
+
+/// One way of unlocking a database. Every slot wraps the same random data key, which is
+/// what the passwords are actually encrypted with, so unlock methods can be added or
+/// changed without touching the passwords.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeySlot {
+    pub kind: KeySlotKind,
+    pub kdf: KdfHeader,
+    pub wrapped_key: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum KeySlotKind {
+    #[serde(rename = "password")]
+    Password,
+}
+
+pub fn generate_data_key() -> Vec<u8> {
+    let mut data_key = vec![0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut data_key);
+    data_key
+}
+
+impl KeySlot {
+    /// Wrap the data key under a key derived from the master password with a fresh salt
+    pub fn wrap_password(data_key: &[u8], password: &str, kdf_params: KdfParams) -> Result<KeySlot, KeySlotError> {
+        let salt = kdf::generate_salt();
+        let master_key = kdf::derive(password, &salt, &kdf_params)?;
+
+        Ok(KeySlot {
+            kind: KeySlotKind::Password,
+            kdf: KdfHeader { id: KdfId::Argon2id, params: kdf_params, salt },
+            wrapped_key: cipher::seal(&master_key.encryption_key, data_key, PASSWORD_SLOT_AD)?,
+        })
+    }
+
+    /// Returns the data key, or `None` if the password doesn't open this slot
+    pub fn unwrap_password(&self, password: &str) -> Result<Option<Vec<u8>>, KeySlotError> {
+        let master_key = kdf::derive(password, &self.kdf.salt, &self.kdf.params)?;
+
+        match cipher::open(&master_key.encryption_key, &self.wrapped_key, PASSWORD_SLOT_AD) {
+            Ok(data_key) => Ok(Some(data_key)),
+            Err(CipherError::AuthenticationFailure) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum KeySlotError {
+    #[error("failed to derive the master key")]
+    KeyDerivation(#[from] KdfError),
+    #[error("failed to wrap the data key")]
+    Cipher(#[from] CipherError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    #[test]
+    fn wrap_unwrap() {
+        let data_key = generate_data_key();
+        let slot = KeySlot::wrap_password(&data_key, "testpass", TEST_PARAMS).unwrap();
+        assert_eq!(slot.unwrap_password("testpass").unwrap(), Some(data_key));
+    }
+
+    #[test]
+    fn unwrap_wrong_password() {
+        let slot = KeySlot::wrap_password(&generate_data_key(), "testpass", TEST_PARAMS).unwrap();
+        assert_eq!(slot.unwrap_password("wrongpass").unwrap(), None);
+    }
+
+    #[test]
+    fn rewrap_keeps_data_key() {
+        let data_key = generate_data_key();
+        let slot = KeySlot::wrap_password(&data_key, "testpass", TEST_PARAMS).unwrap();
+        let unwrapped = slot.unwrap_password("testpass").unwrap().unwrap();
+        let new_slot = KeySlot::wrap_password(&unwrapped, "newpass", TEST_PARAMS).unwrap();
+        assert_ne!(new_slot.kdf.salt, slot.kdf.salt);
+        assert_eq!(new_slot.unwrap_password("newpass").unwrap(), Some(data_key));
+        assert_eq!(new_slot.unwrap_password("testpass").unwrap(), None);
+    }
+}
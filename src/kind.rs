@@ -0,0 +1,291 @@
+//! What an entry holds. Logins are a name, username and password, the other kinds keep their
+//! own details alongside, each checked when it is set.
+
+use std::fmt;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::field::{self, FieldKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    // Only the notes, with no password
+    Note,
+    Card,
+    Identity,
+    SshKey,
+    ApiToken,
+    Wifi,
+}
+
+/// A detail an entry of some kind can have
+pub struct Detail {
+    pub key: &'static str,
+    pub label: &'static str,
+    // Only shown by `get --show-hidden`
+    pub secret: bool,
+    // New entries of the kind must have it
+    pub required: bool,
+    check: Check,
+}
+
+#[derive(Clone, Copy)]
+enum Check {
+    Any,
+    CardNumber,
+    Expiry,
+    Cvv,
+    Pin,
+    Email,
+    Date,
+    PrivateKey,
+    PublicKey,
+    Psk,
+    Security,
+}
+
+const fn detail(key: &'static str, label: &'static str, check: Check) -> Detail {
+    Detail { key, label, secret: false, required: false, check }
+}
+
+const fn secret(key: &'static str, label: &'static str, check: Check) -> Detail {
+    Detail { key, label, secret: true, required: false, check }
+}
+
+const fn required(detail: Detail) -> Detail {
+    Detail { required: true, ..detail }
+}
+
+const CARD: &[Detail] = &[
+    detail("cardholder", "Cardholder", Check::Any),
+    required(secret("number", "Number", Check::CardNumber)),
+    detail("expiry", "Expiry", Check::Expiry),
+    secret("cvv", "CVV", Check::Cvv),
+    secret("pin", "PIN", Check::Pin),
+];
+
+const IDENTITY: &[Detail] = &[
+    detail("full_name", "Full name", Check::Any),
+    detail("email", "Email", Check::Email),
+    detail("phone", "Phone", Check::Any),
+    detail("address", "Address", Check::Any),
+    detail("city", "City", Check::Any),
+    detail("postal_code", "Postal code", Check::Any),
+    detail("country", "Country", Check::Any),
+    detail("birthday", "Birthday", Check::Date),
+];
+
+const SSH_KEY: &[Detail] = &[
+    required(secret("private_key", "Private key", Check::PrivateKey)),
+    detail("public_key", "Public key", Check::PublicKey),
+    secret("passphrase", "Passphrase", Check::Any),
+];
+
+const API_TOKEN: &[Detail] = &[
+    required(secret("token", "Token", Check::Any)),
+    detail("scopes", "Scopes", Check::Any),
+    detail("expires", "Expires", Check::Date),
+];
+
+const WIFI: &[Detail] = &[
+    required(detail("ssid", "SSID", Check::Any)),
+    secret("psk", "Password", Check::Psk),
+    detail("security", "Security", Check::Security),
+];
+
+impl EntryKind {
+    pub fn parse(kind: &str) -> Option<EntryKind> {
+        match kind.to_ascii_lowercase().replace('-', "_").as_str() {
+            "login" => Some(EntryKind::Login),
+            "note" => Some(EntryKind::Note),
+            "card" => Some(EntryKind::Card),
+            "identity" => Some(EntryKind::Identity),
+            "ssh_key" | "ssh" => Some(EntryKind::SshKey),
+            "api_token" | "token" => Some(EntryKind::ApiToken),
+            "wifi" => Some(EntryKind::Wifi),
+            _ => None,
+        }
+    }
+
+    // Only logins use the entry's password, the other kinds keep their secrets in details
+    pub fn is_login(&self) -> bool {
+        *self == EntryKind::Login
+    }
+
+    // The name is encrypted with the kind in the associated data, so an entry can't be made
+    // into another kind without the key. Logins keep the field entries had before kinds.
+    pub fn name_field(self) -> &'static str {
+        match self {
+            EntryKind::Login => "name",
+            EntryKind::Note => "name.note",
+            EntryKind::Card => "name.card",
+            EntryKind::Identity => "name.identity",
+            EntryKind::SshKey => "name.ssh_key",
+            EntryKind::ApiToken => "name.api_token",
+            EntryKind::Wifi => "name.wifi",
+        }
+    }
+
+    pub fn details(self) -> &'static [Detail] {
+        match self {
+            EntryKind::Login | EntryKind::Note => &[],
+            EntryKind::Card => CARD,
+            EntryKind::Identity => IDENTITY,
+            EntryKind::SshKey => SSH_KEY,
+            EntryKind::ApiToken => API_TOKEN,
+            EntryKind::Wifi => WIFI,
+        }
+    }
+
+    pub fn detail(self, key: &str) -> Option<&'static Detail> {
+        self.details().iter().find(|detail| detail.key == key)
+    }
+
+    /// Check a value for one of this kind's details, returning it as it should be saved
+    pub fn check(self, key: &str, value: String) -> Result<String, String> {
+        let Some(detail) = self.detail(key) else {
+            let keys: Vec<&str> = self.details().iter().map(|detail| detail.key).collect();
+            return Err(if keys.is_empty() {
+                format!("{self} entries don't have details")
+            } else {
+                format!("{self} entries have no {key}, they can have {}", keys.join(", "))
+            });
+        };
+        detail.check.check(value).map_err(|problem| format!("The {} {problem}", detail.label.to_lowercase()))
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EntryKind::Login => "login",
+            EntryKind::Note => "note",
+            EntryKind::Card => "card",
+            EntryKind::Identity => "identity",
+            EntryKind::SshKey => "ssh-key",
+            EntryKind::ApiToken => "api-token",
+            EntryKind::Wifi => "wifi",
+        })
+    }
+}
+
+impl Check {
+    fn check(self, value: String) -> Result<String, String> {
+        let digits = |value: &str| value.chars().filter(|c| !c.is_whitespace() && *c != '-').collect::<String>();
+        match self {
+            Check::Any => Ok(value),
+            Check::CardNumber => {
+                let number = digits(&value);
+                if !(12..=19).contains(&number.len()) || !number.chars().all(|c| c.is_ascii_digit()) {
+                    return Err("should be 12 to 19 digits".to_string());
+                }
+                if !luhn(&number) {
+                    return Err("fails the Luhn check, check it was typed correctly".to_string());
+                }
+                Ok(number)
+            },
+            Check::Expiry => {
+                let (month, year) = value.trim().split_once('/').ok_or("should be MM/YY")?;
+                let month: u32 = month.parse().map_err(|_| "should be MM/YY")?;
+                // Checked before slicing, which is by bytes
+                if !(1..=12).contains(&month) || !year.chars().all(|c| c.is_ascii_digit()) {
+                    return Err("should be MM/YY".to_string());
+                }
+                let year = match year.len() {
+                    2 => year.to_string(),
+                    4 => year[2..].to_string(),
+                    _ => return Err("should be MM/YY".to_string()),
+                };
+                Ok(format!("{month:02}/{year}"))
+            },
+            Check::Cvv if value.len() == 3 || value.len() == 4 => all_digits(value, "should be 3 or 4 digits"),
+            Check::Cvv => Err("should be 3 or 4 digits".to_string()),
+            Check::Pin if (4..=12).contains(&value.len()) => all_digits(value, "should be 4 to 12 digits"),
+            Check::Pin => Err("should be 4 to 12 digits".to_string()),
+            Check::Email => field::normalize(FieldKind::Email, value).map_err(|_| "is not a valid email address".to_string()),
+            Check::Date => match NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+                Ok(date) => Ok(date.format("%Y-%m-%d").to_string()),
+                Err(_) => Err("should be a date like 2030-12-31".to_string()),
+            },
+            Check::PrivateKey if value.trim_start().starts_with("-----BEGIN") && value.contains("PRIVATE KEY-----") => Ok(value),
+            Check::PrivateKey => Err("should be a PEM or OpenSSH private key, starting with -----BEGIN".to_string()),
+            Check::PublicKey => {
+                let mut parts = value.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some(algorithm), Some(_)) if algorithm.starts_with("ssh-") || algorithm.starts_with("ecdsa-") || algorithm.starts_with("sk-") => Ok(value.trim().to_string()),
+                    _ => Err("should be an OpenSSH public key, like ssh-ed25519 AAAA...".to_string()),
+                }
+            },
+            Check::Psk => {
+                let hex_key = value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit());
+                let passphrase = (8..=63).contains(&value.len()) && value.chars().all(|c| c.is_ascii() && !c.is_ascii_control());
+                if hex_key || passphrase { Ok(value) } else { Err("should be 8 to 63 characters, or 64 hex digits".to_string()) }
+            },
+            Check::Security => match value.to_ascii_uppercase().as_str() {
+                security @ ("WPA3" | "WPA2" | "WPA" | "WEP") => Ok(security.to_string()),
+                "NONE" | "OPEN" => Ok("none".to_string()),
+                _ => Err("should be WPA3, WPA2, WPA, WEP or none".to_string()),
+            },
+        }
+    }
+}
+
+fn all_digits(value: String, problem: &str) -> Result<String, String> {
+    if value.chars().all(|c| c.is_ascii_digit()) { Ok(value) } else { Err(problem.to_string()) }
+}
+
+/// The Luhn checksum card numbers end with, every second digit from the right is doubled
+pub fn luhn(number: &str) -> bool {
+    let sum: u32 = number
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| match i % 2 {
+            0 => digit,
+            _ if digit * 2 > 9 => digit * 2 - 9,
+            _ => digit * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_numbers() {
+        assert!(luhn("4111111111111111"));
+        assert!(luhn("79927398713"));
+        assert!(!luhn("4111111111111112"));
+        assert_eq!(EntryKind::Card.check("number", "4111 1111-1111 1111".to_string()).unwrap(), "4111111111111111");
+        assert!(EntryKind::Card.check("number", "4111 1111 1111 1112".to_string()).unwrap_err().contains("Luhn"));
+        assert!(EntryKind::Card.check("number", "4111".to_string()).is_err());
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(EntryKind::Card.check("expiry", "3/2030".to_string()).unwrap(), "03/30");
+        assert!(EntryKind::Card.check("expiry", "13/30".to_string()).is_err());
+        assert!(EntryKind::Card.check("expiry", "3/1é1".to_string()).is_err());
+        assert!(EntryKind::Card.check("cvv", "12a".to_string()).is_err());
+        assert_eq!(EntryKind::Wifi.check("security", "wpa2".to_string()).unwrap(), "WPA2");
+        assert!(EntryKind::Wifi.check("psk", "short".to_string()).is_err());
+        assert!(EntryKind::ApiToken.check("expires", "2030-02-30".to_string()).is_err());
+        assert!(EntryKind::SshKey.check("public_key", "ssh-ed25519 AAAAC3Nza me@host".to_string()).is_ok());
+        assert!(EntryKind::SshKey.check("private_key", "not a key".to_string()).is_err());
+        assert!(EntryKind::Identity.check("email", "me@example.com".to_string()).is_ok());
+    }
+
+    #[test]
+    fn unknown_details() {
+        assert!(EntryKind::Card.check("ssid", "home".to_string()).unwrap_err().contains("they can have cardholder, number"));
+        assert!(EntryKind::Login.check("number", "1".to_string()).is_err());
+        assert_eq!(EntryKind::parse("SSH-key"), Some(EntryKind::SshKey));
+    }
+}
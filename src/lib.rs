@@ -5,65 +5,329 @@
 
 mod password;
 mod database;
+mod atomic;
+mod attachment;
+mod audit;
+mod backup;
+mod breach;
+mod cipher;
+mod expiry;
+mod field;
+mod folder;
+mod format;
+mod generator;
+mod kdf;
+mod keyslot;
+mod kind;
+mod lock;
+mod migration;
+mod otp;
+mod search;
+mod secret;
+mod strength;
+mod trash;
 pub mod config;
+pub mod passphrase;
 
 use std::error::Error;
+use std::path::Path;
+use std::io::{self, IsTerminal, Write};
+
+use chrono::{DateTime, Local, Utc};
 
 use crate::config::Config;
 use crate::config::Command;
-use crate::database::Database;
+use crate::config::FieldOptions;
+use crate::breach::BreachList;
+use crate::database::{Database, DatabaseError};
+use crate::field::FieldKind;
+use crate::format::FORMAT_VERSION;
+use crate::kdf::KdfParams;
+use crate::lock::LockMode;
+use crate::migration::MigrationError;
+use crate::otp::Otp;
+use crate::password::{Attachment, Password};
+use crate::secret::{SecretSource, Secrets};
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let mut secrets = Secrets::open(config.secret_source)?;
 
     match config.command {
-        config::Command::None => {
-            let master_password = rpassword::prompt_password("Please enter the master password for the new database\n").unwrap();
-            Database::new(config.database_name, master_password)?;
+        config::Command::Create(kdf_options, layout_options) => {
+            if config.secret_source == SecretSource::Prompt {
+                let suggestion = passphrase::suggest_master_password();
+                println!("Need a master password? Here is a random passphrase ({:.0} bits):\n    {}",
+                    suggestion.entropy_bits,
+                    suggestion.phrase
+                );
+            }
+            let master_password = secrets.read_new("Please enter the master password for the new database")?;
+            check_master_password(&master_password, config.min_master_score)?;
+            Database::create(config.database_name + ".oxd", &master_password, kdf_options.apply(KdfParams::default()), layout_options)?;
             return Ok(())
         },
         config::Command::Help => {
             print_help();
             return Ok(())
-        }
+        },
+        config::Command::Generate(generator_options) => {
+            println!("{}", generator::generate(&generator_options.policy)?);
+            return Ok(())
+        },
+        config::Command::BuildBreachIndex { text_path, index_path } => {
+            let hashes = breach::build_index(Path::new(&text_path), Path::new(&index_path))?;
+            println!("Wrote {hashes} hashes to {index_path}");
+            return Ok(())
+        },
+        config::Command::Passphrase(passphrase_options) => {
+            let passphrase = passphrase::generate(&passphrase_options)?;
+            println!("{}", passphrase.phrase);
+            eprintln!("Entropy: {:.1} bits", passphrase.entropy_bits);
+            return Ok(())
+        },
+        config::Command::ListBackups => {
+            let _database_lock = Database::lock(&config.database_name, LockMode::Shared, config.lock_timeout)?;
+            list_backups(&config.database_name)?;
+            return Ok(())
+        },
+        // Restoring doesn't unlock the current database, it may be the reason for restoring
+        config::Command::RestoreBackup(number) => {
+            let entered_password = secrets.read("Please enter the master password for the backup")?;
+            let _database_lock = Database::lock(&config.database_name, LockMode::Exclusive, config.lock_timeout)?;
+            if Database::restore_backup(&config.database_name, number, &entered_password)? {
+                println!("Restored backup {number}, the database it replaced was kept as backup 1");
+            } else {
+                println!("The password you entered was incorrect");
+            }
+            return Ok(())
+        },
         _ => ()
     }
 
+    let outdated = Database::format_version(&config.database_name)? < FORMAT_VERSION;
+    let entered_password = secrets.read("Please enter the database master password")?;
+
+    // Lock out other writers from here until the command is done, the format version is
+    // read again under the lock in case another process migrated the file first
+    let lock_mode = if config.command.is_read_only() && !outdated { LockMode::Shared } else { LockMode::Exclusive };
+    let _database_lock = Database::lock(&config.database_name, lock_mode, config.lock_timeout)?;
+    let format_version = Database::format_version(&config.database_name)?;
+
+    // Databases written by older versions are rewritten into the current format once the
+    // master password is known
+    if format_version < FORMAT_VERSION {
+        let backup_path = match Database::migrate(&config.database_name, &entered_password) {
+            Err(DatabaseError::Migration(MigrationError::IncorrectPassword)) => {
+                println!("The password you entered was incorrect");
+                return Ok(())
+            },
+            result => result?,
+        };
+        eprintln!("Migrated the database from format version {format_version} to {FORMAT_VERSION}, the original was kept at {backup_path}");
+    }
+
     // Database is encrypted at this point
     let mut database = Database::load(&config.database_name)?;
 
-    // Check the entered password against the stored master password
-    let entered_password = rpassword::prompt_password("Please enter the database master password\n").unwrap();
-    if !database.verify_master_password(&entered_password) {
+    // Check the entered password against the database's key slots
+    let Some(encryption_key) = database.unlock(&entered_password)? else {
         println!("The password you entered was incorrect");
         return Ok(())
-    }
+    };
 
     match config.command {
 
-        config::Command::List => database.list_passwords(&entered_password)?,
+        config::Command::List(list_options) => database.list_passwords(&encryption_key, &list_options)?,
+
+        config::Command::New { name, kind, user, generate, mut fields, .. } => {
+            // Other kinds keep their secrets in details, but can still have a generated password
+            let pass = match &generate {
+                Some(generator_options) => Some(generator::generate(&generator_options.policy)?),
+                None if kind.is_login() => Some(secrets.read_new("Please enter the new password")?),
+                None => None,
+            };
+            read_field_secrets(&mut secrets, &mut fields)?;
+            let generated = generate.as_ref().map(|generator_options| generator_options.show).zip(pass.clone());
+            database.new_password(config.database_name, &encryption_key, Command::New { name, kind, user, pass, generate, fields })?;
+            if let Some((show, password)) = generated {
+                print_generated(show, &password);
+            }
+        },
+
+        config::Command::Edit { item, name, user, ask_pass, generate, mut fields, .. } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let pass = match &generate {
+                Some(generator_options) => Some(generator::generate(&generator_options.policy)?),
+                None if ask_pass => Some(secrets.read_new("Please enter the new password")?),
+                None => None,
+            };
+            read_field_secrets(&mut secrets, &mut fields)?;
+            let generated = generate.as_ref().map(|generator_options| generator_options.show).zip(pass.clone());
+            database.edit_password(config.database_name, &encryption_key, Command::Edit { item, name, user, pass, ask_pass, generate, fields })?;
+            if let Some((show, password)) = generated {
+                print_generated(show, &password);
+            }
+        },
+
+        config::Command::Delete { item, yes } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            if !yes {
+                let password = database.get_password(&encryption_key, Command::Get { item: item.clone(), show_hidden: false })?;
+                if !confirm(&format!("Move {} - {} to the trash?", password.name, password.username))? {
+                    println!("Nothing was deleted");
+                    return Ok(());
+                }
+            }
+            database.del_password(config.database_name, &encryption_key, Command::Delete { item, yes })?
+        },
+
+        config::Command::Get { item, show_hidden } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let password = database.get_password(&encryption_key, Command::Get { item, show_hidden })?;
+            print_password(&password, show_hidden);
+            database.record_access(config.database_name, &encryption_key, password.id)?;
+        },
+
+        config::Command::History(item) => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let password = database.get_history(&encryption_key, Command::History(item))?;
+            if password.history.is_empty() {
+                println!("{} has no previous passwords", password.name);
+            }
+            for (number, previous) in password.history.iter().enumerate() {
+                println!("{number:>2}  replaced {replaced}  {pass}",
+                    number=number + 1,
+                    replaced=password::format_timestamp(&Some(previous.replaced.clone()), "unknown"),
+                    pass=previous.password
+                );
+            }
+        },
+
+        config::Command::Otp(item) => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let code = database.otp_code(config.database_name, &encryption_key, Command::Otp(item))?;
+            match (code.remaining_seconds, code.counter) {
+                (Some(remaining), _) => println!("{} (valid for {remaining} more seconds)", code.code),
+                (_, Some(counter)) => println!("{} (counter {counter})", code.code),
+                _ => println!("{}", code.code),
+            }
+        },
+
+        config::Command::Move { item, folder } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            database.move_password(config.database_name, &encryption_key, Command::Move { item, folder })?;
+        },
+
+        config::Command::Tag { item, add, remove } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            database.tag_password(config.database_name, &encryption_key, Command::Tag { item, add, remove })?;
+        },
+
+        config::Command::Attach { item, file, name } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let attachment = database.attach_file(config.database_name, &encryption_key, Command::Attach { item, file, name })?;
+            println!("Attached {} ({})", attachment.name, attachment::format_size(attachment.size));
+        },
+
+        config::Command::Attachments(item) => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let password = database.get_attachments(&encryption_key, Command::Attachments(item))?;
+            if password.attachments.is_empty() {
+                println!("{} has no attachments", password.name);
+            }
+            let name_width = password.attachments.iter().map(|attachment| attachment.name.chars().count()).max().unwrap_or(0);
+            for attachment in &password.attachments {
+                println!("{name:<name_width$}  {size:>10}  added {added}",
+                    name=attachment.name,
+                    size=attachment::format_size(attachment.size),
+                    added=password::format_timestamp(&Some(attachment.added.clone()), "unknown")
+                );
+            }
+        },
+
+        config::Command::Extract { item, name, output } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            let attachment = database.get_attachment(&encryption_key, Command::Extract { item, name, output: None })?;
+            extract_attachment(&config.database_name, &encryption_key, &attachment, output)?;
+        },
+
+        config::Command::Detach { item, name } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            database.detach_file(config.database_name, &encryption_key, Command::Detach { item, name })?;
+        },
 
-        config::Command::New { name, user, pass } => {
-            database.new_password(config.database_name, entered_password, Command::New { name, user, pass })?
+        // Exits with an error when anything is overdue, so scheduled checks can fail on it
+        config::Command::Due { within } => {
+            let due = database.due_passwords(&encryption_key, Command::Due { within })?;
+            if due.is_empty() {
+                println!("No passwords are due to be changed in the next {} days", within.num_days());
+            }
+            let now = Utc::now();
+            for (date, password) in &due {
+                let when = if *date <= now { "overdue since" } else { "due" };
+                println!("{id}  {name} - {user}  {when} {date}",
+                    id=password.short_id(),
+                    name=password.name,
+                    user=password.username,
+                    date=password::format_date(*date)
+                );
+            }
+            let overdue = due.iter().filter(|(date, _)| *date <= now).count();
+            if overdue > 0 {
+                return Err(format!("{overdue} of {} passwords are overdue to be changed", database.passwords.len()).into());
+            }
         },
 
-        config::Command::Edit { item, name, user, pass } => {
-            database.edit_password(config.database_name, entered_password, Command::Edit { item, name, user, pass })?
+        config::Command::Restore { item, number } => {
+            let item = choose_password(&database, &encryption_key, item)?;
+            database.restore_password(config.database_name, &encryption_key, Command::Restore { item, number })?;
         },
 
-        config::Command::Delete(id) => database.del_password(config.database_name, Command::Delete(id))?,
+        config::Command::ChangeMaster(_) => {
+            let new_password = secrets.read_new("Please enter the new master password")?;
+            check_master_password(&new_password, config.min_master_score)?;
+            let new_password = Some(new_password);
+            database.change_master_password(config.database_name, &encryption_key, Command::ChangeMaster(new_password))?;
+        },
+
+        config::Command::UpdateKdf(kdf_options) => {
+            database.update_kdf(config.database_name, &encryption_key, &entered_password, Command::UpdateKdf(kdf_options))?;
+        },
 
-        config::Command::Get(id) => {
-            let password = database.get_password(&entered_password, Command::Get(id))?;
-            println!("Name: {name}\nUser: {user}\nPass: {pass}",
-                name=password.name,
-                user=password.username,
-                pass=password.password
-            );
+        config::Command::SetLayout(layout_options) => {
+            database.set_layout(config.database_name, &encryption_key, Command::SetLayout(layout_options))?;
         },
 
-        config::Command::ChangeMaster(new_password) => {
-            database.change_master_password(config.database_name, &entered_password, Command::ChangeMaster(new_password))?;
-            
+        config::Command::Audit { max_age_days, json, breaches } => {
+            let mut breaches = breaches.map(|path| BreachList::open(Path::new(&path))).transpose()?;
+            let report = database.audit_passwords(&encryption_key, max_age_days, breaches.as_mut())?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{report}");
+            }
+        },
+
+        config::Command::ListTrash => database.list_trash(&encryption_key)?,
+
+        config::Command::RestoreTrash(item) => {
+            database.restore_from_trash(config.database_name, &encryption_key, Command::RestoreTrash(item))?;
+        },
+
+        config::Command::EmptyTrash { yes } => {
+            if !yes && !confirm(&format!("Delete the {} passwords in the trash for good?", database.trash.len()))? {
+                println!("Nothing was deleted");
+                return Ok(());
+            }
+            database.empty_trash(config.database_name, &encryption_key, Command::EmptyTrash { yes })?;
+        },
+
+        config::Command::KeepTrash(days) => {
+            database.set_trash_days(config.database_name, &encryption_key, Command::KeepTrash(days))?;
+        },
+
+        config::Command::KeepBackups(keep) => {
+            database.set_backups(config.database_name, &encryption_key, Command::KeepBackups(keep))?;
         },
 
         _ => ()
@@ -72,51 +336,457 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn check_master_password(master_password: &str, min_score: u8) -> Result<(), Box<dyn Error>> {
+    let strength = strength::estimate(master_password, &[]);
+    if strength.score < min_score {
+        return Err(format!("The master password is too weak, it needs a score of at least {min_score} but has {strength}\n\
+A passphrase from the passphrase command is strong enough").into());
+    }
+    Ok(())
+}
+
+// Read the secrets asked for by the field options, after any new password
+fn read_field_secrets(secrets: &mut Secrets, fields: &mut FieldOptions) -> Result<(), Box<dyn Error>> {
+    for field in fields.set_fields.iter_mut().filter(|field| field.kind == FieldKind::Hidden) {
+        field.value = secrets.read_new(&format!("Please enter the value of {}", field.name))?;
+    }
+    for (key, value) in fields.secret_details.iter_mut().filter(|(_, value)| value.is_none()) {
+        *value = Some(secrets.read_new(&format!("Please enter the {}", key.replace('_', " ")))?);
+    }
+    if fields.ask_otp {
+        // Saved in one form whatever was entered, so the HOTP counter can be updated
+        let otp = Otp::parse(&secrets.read("Please enter the otpauth:// URI or base32 secret for one-time passwords")?)?;
+        fields.otp = Some(otp.to_uri());
+    }
+    Ok(())
+}
+
+fn print_password(password: &Password, show_hidden: bool) {
+    println!("Id:        {id}\nName:      {name}\nUser:      {user}",
+        id=password.id,
+        name=password.name,
+        user=password.username
+    );
+    if password.kind.is_login() || !password.password.is_empty() {
+        println!("Pass:      {}", password.password);
+    }
+    if !password.kind.is_login() {
+        println!("Kind:      {}", password.kind);
+    }
+    // In the order of the kind's schema, multi-line values like keys start on their own line
+    for detail in password.kind.details() {
+        let Some(value) = password.details.get(detail.key) else { continue };
+        let value = if detail.secret && !show_hidden { "(hidden, use --show-hidden to see it)" } else { value.trim_end() };
+        let label = format!("{}:", detail.label);
+        if value.contains('\n') {
+            println!("{label}\n{value}");
+        } else {
+            println!("{label:<10} {value}");
+        }
+    }
+    if let Some(folder) = &password.folder {
+        println!("Folder:    {folder}");
+    }
+    if !password.tags.is_empty() {
+        println!("Tags:      {}", password.tags.join(", "));
+    }
+    for url in &password.urls {
+        println!("URL:       {url}");
+    }
+    for field in &password.fields {
+        let value = match field.kind {
+            FieldKind::Hidden if !show_hidden => "(hidden, use --show-hidden to see it)",
+            _ => &field.value,
+        };
+        println!("{name}: {value}", name=field.name);
+    }
+    for attachment in &password.attachments {
+        println!("Attached:  {} ({})", attachment.name, attachment::format_size(attachment.size));
+    }
+    match password.otp.as_deref().map(Otp::parse) {
+        Some(Ok(otp)) => println!("OTP:       {otp}, use the otp command for a code"),
+        Some(Err(e)) => println!("OTP:       {e}"),
+        None => (),
+    }
+    if let Some(notes) = &password.notes {
+        println!("Notes:\n{notes}");
+    }
+    if let Some(expires) = &password.expires {
+        println!("Expires:   {expires}");
+    }
+    if let Some(days) = &password.rotate_days {
+        println!("Rotate:    every {days} days");
+    }
+    if let Some(due) = password.due() {
+        println!("Due:       {}{}", password::format_date(due), if due <= Utc::now() { ", overdue" } else { "" });
+    }
+    println!("Created:   {created}\nModified:  {modified}\nChanged:   {changed}\nLast used: {used}",
+        created=password::format_timestamp(&password.created, "unknown"),
+        modified=password::format_timestamp(&password.modified, "unknown"),
+        changed=password::format_timestamp(&password.password_changed, "unknown"),
+        used=password::format_timestamp(&password.last_accessed, "never")
+    );
+}
+
+// Generated passwords go straight into the database, they are only printed if asked for once
+// they have been saved
+fn print_generated(show: bool, password: &str) {
+    if show {
+        println!("{password}");
+    } else {
+        println!("Saved a generated {} character password, use the get command to see it", password.chars().count());
+    }
+}
+
+// Turn the id or search given to get/edit/delete into the id of a single password, asking
+// which one was meant if several match. Without a terminal to ask on, the error listing the
+// matches is returned instead.
+fn choose_password(database: &Database, encryption_key: &[u8], query: String) -> Result<String, Box<dyn Error>> {
+    let candidates = match database.find_password(encryption_key, &query) {
+        Ok(password_id) => return Ok(database.passwords[password_id].id.to_string()),
+        Err(DatabaseError::AmbiguousMatch(_, candidates)) if io::stdin().is_terminal() => candidates,
+        Err(e) => return Err(e.into()),
+    };
+
+    println!("\"{query}\" matches more than one password:");
+    for (number, candidate) in candidates.iter().enumerate() {
+        println!("{number}. {id}  {name} - {user}",
+            number = number + 1,
+            id = candidate.short_id(),
+            name = candidate.name,
+            user = candidate.username
+        );
+    }
+    print!("Which one did you mean? [1-{}] ", candidates.len());
+    io::stdout().flush()?;
+
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    match choice.trim().parse::<usize>() {
+        Ok(number) if (1..=candidates.len()).contains(&number) => Ok(candidates[number - 1].id.to_string()),
+        _ => Err(format!("\"{}\" is not one of the listed passwords, nothing was changed", choice.trim()).into()),
+    }
+}
+
+// `-o -` writes to stdout, files are only readable by their owner. Without `-o` the file is
+// named after the attachment, and an existing file is never replaced.
+fn extract_attachment(database_name: &str, decryption_key: &[u8], attachment: &Attachment, output: Option<String>) -> Result<(), Box<dyn Error>> {
+    if output.as_deref() == Some("-") {
+        attachment::extract(database_name, decryption_key, &attachment.id, io::stdout().lock())?;
+        return Ok(());
+    }
+
+    let replace = output.is_some();
+    let path = output.unwrap_or_else(|| attachment.name.clone());
+    attachment::extract_to(database_name, decryption_key, &attachment.id, Path::new(&path), replace)
+        .map_err(|e| format!("Failed to extract {} to {path}: {e}", attachment.name))?;
+    println!("Extracted {} to {path}", attachment.name);
+    Ok(())
+}
+
+// Ask a yes or no question, without a terminal to ask on the command fails instead of
+// guessing, and `--yes` has to be given to go ahead
+fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    if !io::stdin().is_terminal() {
+        return Err(format!("{question} Pass --yes to confirm when not running in a terminal").into());
+    }
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn list_backups(database_name: &str) -> Result<(), Box<dyn Error>> {
+    let backups = backup::list(database_name)?;
+    if backups.is_empty() {
+        println!("There are no backups of {database_name}");
+    }
+    for backup in backups {
+        let modified: DateTime<Local> = backup.modified.into();
+        println!("{number}. {path} - {modified} - {size} bytes",
+            number = backup.number,
+            path = backup.path,
+            modified = modified.format("%Y-%m-%d %H:%M:%S"),
+            size = backup.size
+        );
+    }
+    Ok(())
+}
+
 fn print_help() {
     let help_string = "Usage: <COMMAND|DATABASE_NAME> <INPUTS>...
-Simple commandline password manager with basic SHA2 master password hashing and key-based base64 encryption.
+Simple commandline password manager with Argon2id master password key derivation and XChaCha20-Poly1305 encryption.
     
-    oxidizepw new <db_namename>
+    oxidizepw new <db_namename> [-m <memory_kib>] [-t <iterations>] [-p <parallelism>] [--sealed|--entries] [--pad <bytes>]
         Make a new database file. New files will be given the .oxd extension.
         db_namename: Name for the new database
+        memory_kib: Argon2id memory cost in KiB (default 65536)
+        iterations: Argon2id iteration count (default 3)
+        parallelism: Argon2id lane count (default 1)
+        --sealed: Encrypt the whole database as one blob, hiding the number and size of entries
+        --entries: Encrypt each password field separately (default)
+        bytes: Pad sealed databases up to a multiple of this size (default 0, no padding)
     
+    oxidizepw generate [-l <length>] [--max-length <length>] [--no-lower] [--no-upper] [--no-digits]
+                       [--no-symbols] [--symbols <symbols>] [--no-ambiguous]
+        Print a random password, with at least one character from every class that isn't left out.
+        length: Number of characters (default 20), --max-length caps this for sites with a limit
+        --no-lower, --no-upper, --no-digits, --no-symbols: Leave out a class of characters
+        symbols: The only symbols to use, for sites that accept a few of them
+        --no-ambiguous: Leave out characters that are easily confused, like 0 and O or 1, l and I
+
+    oxidizepw passphrase [-w <words>] [--short] [--separator <separator>] [--capitalize] [--digit]
+        Print a random passphrase of words from the EFF wordlists, and its entropy in bits. When a
+        new database is made, one of these is suggested for the master password.
+        words: Number of words (default 6)
+        --short: Use the EFF short wordlist, its words are quicker to type but each adds fewer bits
+        separator: What goes between words (default -)
+        --capitalize: Start every word with a capital letter
+        --digit: Add a random digit to one of the words
+
+    oxidizepw breach-index <text_file> <index_file>
+        Convert the Pwned Passwords text file ordered by hash into a smaller index that audit
+        --breaches searches faster.
+
     oxidizepw help|-h
         Print this help output :)
     
-    oxidizepw <db_file> list
-        List all passwords in the database. The index given to each password how passwords
-        are identified the edit/delete/get commands.
+    Any command on a database file waits up to 10 seconds for other oxidizepw processes using the
+    same file to finish, add --lock-timeout <seconds> anywhere on the command line to change this.
+
+    New master passwords have to score at least 3 out of 4 for how hard they are to guess, add
+    --min-score <score> anywhere on the command line to change this. Weak passwords can still be
+    saved in the database, but a warning is printed.
+
+    Passwords are never taken as arguments, you are asked for them instead and new ones are asked
+    for twice. To script oxidizepw, add --password-stdin or --password-fd <fd> anywhere on the
+    command line and give one password per line, in the order they would be asked for: the master
+    password first, then any new password, then the values of any hidden fields, then any asked
+    for details, then any one-time password secret.
+
+    oxidizepw <db_file> list [--sort <key>] [--older-than <age>] [--folder <folder>] [--tag <tag>] [--kind <kind>] [--tree]
+        List all passwords in the database, each with its folder, the start of its id, its tags
+        and when it was last modified and used. Ids never change, so they can be kept in scripts.
+        Passwords overdue to be changed end with OVERDUE, see the due command.
         db_file: Database file
+        --sort: Sort by name, created, modified, changed (when the password was last changed) or
+            used (when it was last shown by get), oldest first
+        --older-than: Only list passwords last changed longer ago than this, like 180d, given in
+            hours (h), days (d), weeks (w), months (m) or years (y). Passwords saved before
+            changes were recorded are always listed.
+        --folder: Only list passwords in this folder, or the folders inside it. --folder / lists
+            the passwords that aren't in a folder.
+        --tag: Only list passwords with this tag, give it more than once to list passwords with
+            all of the tags
+        --kind: Only list entries of this kind, see the new command
+        --tree: Show the passwords under their folders
     
-    oxidizepw <db_file> new <name> <username> <password>
-        Adds a new password to the database.
+    oxidizepw <db_file> new <name> [<username>] [--kind <kind>] [--generate [<generate options>] [--show]] [<field options>]
+        Adds a new password to the database, asking for the password to save.
         db_file: Database file
         name: Name of the new password
         username: Username associated with the password
-        password: The new password to save
+        --kind: What the entry holds, a login (the default) or one of these, which aren't asked
+            for a password and keep their details instead. Details marked * must be given and
+            secret ones are only shown by get --show-hidden.
+            note: Only notes
+            card: cardholder, number* (secret, Luhn checked), expiry (MM/YY), cvv (secret),
+                pin (secret)
+            identity: full_name, email, phone, address, city, postal_code, country, birthday
+                (YYYY-MM-DD)
+            ssh-key: private_key* (secret), public_key, passphrase (secret)
+            api-token: token* (secret), scopes, expires (YYYY-MM-DD)
+            wifi: ssid*, psk (secret, 8 to 63 characters), security (WPA3, WPA2, WPA, WEP
+                or none)
+        --generate: Save a random password instead, takes the options of the generate command.
+            The password is only printed with --show.
+        Field options, each can be given more than once:
+        --url <url>: A website the password is for, https:// is added if there is no scheme
+        --notes <text>: Free form notes
+        --field [<type>:]<name>=<value>: A custom field, the type is text (the default), url or
+            email. Fields with the same name as an existing field replace it.
+        --hidden <name>: A hidden custom field, like a security question answer, asking for its
+            value. It is only shown by get --show-hidden.
+        --otp: Ask for an otpauth:// URI or base32 secret to make two factor codes with, see the
+            otp command
+        --detail <key>=<value>: Set one of the kind's details, secret ones can't be given here
+        --detail <key>: Ask for the value of one of the kind's details
+        --detail-file <key>=<file>: Read the value of a detail from a file, like an SSH key
+        --expires <date>: A date the password has to be changed by, like 2030-12-31
+        --rotate <age>: How long the password can be kept before it has to be changed again,
+            in whole days like 90d, 12w, 3m or 1y, up to 100 years. Changing the password starts it
+            again.
 
-    oxidizepw <db_file> edit <id> [-n <name>] [-u <username>] [-p <password>]
+    oxidizepw <db_file> edit <id|search> [-n <name>] [-u <username>] [-p | --generate [<generate options>] [--show]]
         Edits a specific password, you have the option to edit any or all properties of the
         password.
         db_file: Database file
-        id: The id of the password to edit, use the list command to find the password id
+        id: The password to edit, given as its id, the start of its id (at least 4 characters)
+            or a search of names and usernames. If the search matches several passwords you
+            are asked which one you meant, or the command fails when not run in a terminal.
         name: The new name to give the password
         username: The new username to set for the password
-        password: The new password
+        -p: Ask for a new password
+        --generate: Replace the password with a random one, see the new command
+        Takes the field options of the new command, empty notes remove the notes, and:
+        --remove-url <url>: Remove a URL
+        --remove-field <name>: Remove a custom field
+        --remove-otp: Remove the one-time password secret
+        --remove-detail <key>: Remove a detail the kind doesn't need
+        --expires never, --rotate never: Remove the expiry date or rotation interval
+
+    oxidizepw <db_file> delete <id|search> [--yes]
+        Move a specific password to the trash, after asking to confirm.
+        db_file: Database file
+        id: The password to delete, given as its id, the start of its id (at least 4 characters)
+            or a search of names and usernames. If the search matches several passwords you
+            are asked which one you meant, or the command fails when not run in a terminal.
+        --yes, -y: Delete without asking, needed when not run in a terminal
+
+    oxidizepw <db_file> get <id|search> [--show-hidden]
+        Prints the information associated with a specific password, and when it was created,
+        modified, changed and last used. The time it was used is then updated.
+        db_file: Database file
+        id: The password to print, given as its id, the start of its id (at least 4 characters)
+            or a search of names and usernames. If the search matches several passwords you
+            are asked which one you meant, or the command fails when not run in a terminal.
+        --show-hidden: Print the values of hidden fields and secret details too
+
+    oxidizepw <db_file> otp <id|search>
+        Prints the current two factor code of a specific password, added with --otp. TOTP codes
+        (RFC 6238) are shown with the seconds they are still valid for, HOTP codes (RFC 4226)
+        move the password's counter on to the next code.
+        db_file: Database file
+        id: The password to make a code for, see the get command
+
+    oxidizepw <db_file> mv <id|search> <folder>
+        Move a specific password to a folder, like prod/aws. Folders don't need to be made
+        first, and a folder of / moves the password out of any folder.
+        db_file: Database file
+        id: The password to move, see the get command
+
+    oxidizepw <db_file> tag <id|search> [<tag>...] [--remove <tag>...]
+        Add or remove tags on a specific password. Tags are single words, matched ignoring case.
+        db_file: Database file
+        id: The password to tag, see the get command
+        tag: A tag to add
+        --remove, -r: A tag to remove
+
+    oxidizepw <db_file> attach <id|search> <file> [--name <name>]
+        Keep a file with a specific password, like recovery codes or a key file, up to 64 MiB. It
+        is encrypted into its own file in <db_file>.attachments/, which backups don't include.
+        db_file: Database file
+        id: The password to attach the file to, see the get command
+        file: The file to attach
+        --name: The name to keep it under, the file's name by default
+
+    oxidizepw <db_file> attachments <id|search>
+        Prints the names and sizes of the files attached to a specific password.
+        db_file: Database file
+        id: The password to list the attachments of, see the get command
+
+    oxidizepw <db_file> extract <id|search> <name> [-o <path>]
+        Decrypt an attached file. Without -o it is written to a new file with the attachment's
+        name in the current directory.
+        db_file: Database file
+        id: The password the file is attached to, see the get command
+        name: The name of the attachment
+        -o, --output: The file to write to, replacing it if it exists, or - to print it
+
+    oxidizepw <db_file> detach <id|search> <name>
+        Remove an attached file for good.
+        db_file: Database file
+        id: The password the file is attached to, see the get command
+        name: The name of the attachment
+
+    oxidizepw <db_file> due [--within <age>]
+        Lists the passwords that are overdue to be changed, or will be soon, from their expiry
+        date or rotation interval, whichever comes first. Exits with an error if any are
+        overdue, for checks run from cron or CI.
+        db_file: Database file
+        --within: How far ahead to look for passwords that will soon be due, like 30d, 14 days
+            by default
+
+    oxidizepw <db_file> history <id|search>
+        Prints the previous passwords of a specific password, numbered from 1 for the most
+        recent, with when each was replaced. The last 10 are kept.
+        db_file: Database file
+        id: The password to print the history of, see the get command
+
+    oxidizepw <db_file> restore <id|search> <n>
+        Go back to a previous password, the current one is kept in the history in its place.
+        db_file: Database file
+        id: The password to restore, see the get command
+        n: The number of the previous password, use the history command to find it
+
+    oxidizepw <db_file> updatepass
+        Update the master password of the database, asking for the new one
+        db_file: Database file
+
+    oxidizepw <db_file> updatekdf [-m <memory_kib>] [-t <iterations>] [-p <parallelism>]
+        Re-derive the master password key with new Argon2id parameters, any parameter left out keeps
+        its current value.
+        db_file: Database file
+        memory_kib: Argon2id memory cost in KiB
+        iterations: Argon2id iteration count
+        parallelism: Argon2id lane count
+
+    oxidizepw <db_file> layout [--sealed|--entries] [--pad <bytes>]
+        Change how the database is laid out on disk, any option left out keeps its current value.
+        db_file: Database file
+        --sealed: Encrypt the whole database as one blob, hiding the number and size of entries
+        --entries: Encrypt each password field separately
+        bytes: Pad sealed databases up to a multiple of this size, 0 disables padding
+
+    oxidizepw <db_file> audit [--max-age <days>] [--breaches <path>] [--json]
+        Check every password for problems: empty, reused by another entry, weak, containing the
+        entry's name or username, not changed for a long time, or seen in a data breach.
+        db_file: Database file
+        days: Passwords not changed for longer than this are reported as stale (default 365)
+        path: A downloaded copy of the Have I Been Pwned Pwned Passwords SHA-1 list to look each
+            password up in, nothing is sent over the network. This can be the text file ordered
+            by hash, an index of it made by breach-index, or a directory of range files named
+            after the first 5 characters of the hashes they hold. Passwords whose range file is
+            missing are reported as not checked.
+        --json: Print the report as JSON instead of a table
+
+    oxidizepw <db_file> backups [list]
+        List the backups kept of the database, numbered from the most recent. A backup is made
+        every time the database is saved.
+        db_file: Database file
+
+    oxidizepw <db_file> backups restore <n>
+        Replace the database with backup n, after checking that every password in it decrypts. The
+        database being replaced becomes backup 1, so a restore can be undone.
+        db_file: Database file
+        n: The number of the backup to restore, use the backups list command to find it
+
+    oxidizepw <db_file> backups keep <n>
+        Set how many backups to keep (default 5), 0 turns backups off.
+        db_file: Database file
+        n: The number of backups to keep
+
+    oxidizepw <db_file> trash [list]
+        List the deleted passwords in the trash, and when each will be removed for good.
+        db_file: Database file
 
-    oxidizepw <db_file> delete <id>
-        Delete a specific password from the database.
+    oxidizepw <db_file> trash restore <id|search>
+        Move a password out of the trash, back into the database.
         db_file: Database file
-        id: The id of the password to delete, use the list command to find the password id
+        id: The password to restore, given as its id, the start of its id or a search
 
-    oxidizepw <db_file> get <id>
-        Prints the information associated with a specific password.
+    oxidizepw <db_file> trash empty [--yes]
+        Delete every password in the trash and their attachments for good, after asking to
+        confirm.
         db_file: Database file
-        id: The id of the password to print, use the list command to find the password id
+        --yes, -y: Empty the trash without asking
 
-    oxidizepw <db_file> updatepass <new_master_pass>
-        Update the master password of the database
+    oxidizepw <db_file> trash keep <days>
+        Set how many days deleted passwords stay in the trash (default 30, at most 36500), 0
+        deletes them straight away.
         db_file: Database file
-        new_master_pass: The new master password for the database";
+        days: The number of days to keep deleted passwords";
     println!("{}", help_string);
 }
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io,
+    thread,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockMode {
+    // Any number of readers can hold the lock at once
+    Shared,
+    // Held for the whole load-modify-save cycle so concurrent writers can't lose updates
+    Exclusive,
+}
+
+/// An advisory lock on a database, released when dropped. The lock is taken on a separate
+/// `.lock` file because saves replace the database file, and a lock held on the old file
+/// wouldn't stop anyone opening the new one.
+pub struct DatabaseLock {
+    _file: File,
+}
+
+pub fn acquire(file_path: &str, mode: LockMode, timeout: Duration) -> Result<DatabaseLock, LockError> {
+    let lock_path = format!("{file_path}.lock");
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lock_path)?;
+
+    let started = Instant::now();
+    loop {
+        let result = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match result {
+            Ok(()) => return Ok(DatabaseLock { _file: file }),
+            Err(TryLockError::WouldBlock) if started.elapsed() < timeout => thread::sleep(RETRY_INTERVAL),
+            Err(TryLockError::WouldBlock) => return Err(LockError::Timeout(file_path.to_string(), timeout)),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("timed out after {}s waiting for another process using `{0}` to finish", .1.as_secs_f32())]
+    Timeout(String, Duration),
+    #[error("failed to lock the database")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn test_path(dir: &tempfile::TempDir) -> String {
+        dir.path().join("test.oxd").to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn exclusive_blocks_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let _lock = acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT).unwrap();
+        assert!(matches!(acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT), Err(LockError::Timeout(..))));
+    }
+
+    #[test]
+    fn exclusive_blocks_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let _lock = acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT).unwrap();
+        assert!(matches!(acquire(&test_path(&dir), LockMode::Shared, TEST_TIMEOUT), Err(LockError::Timeout(..))));
+    }
+
+    #[test]
+    fn shared_allows_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let _first = acquire(&test_path(&dir), LockMode::Shared, TEST_TIMEOUT).unwrap();
+        let _second = acquire(&test_path(&dir), LockMode::Shared, TEST_TIMEOUT).unwrap();
+        assert!(matches!(acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT), Err(LockError::Timeout(..))));
+    }
+
+    #[test]
+    fn released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT).unwrap();
+        drop(lock);
+        assert!(acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT).is_ok());
+    }
+
+    #[test]
+    fn waits_for_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = acquire(&test_path(&dir), LockMode::Exclusive, TEST_TIMEOUT).unwrap();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(lock);
+        });
+        assert!(acquire(&test_path(&dir), LockMode::Exclusive, Duration::from_secs(5)).is_ok());
+        releaser.join().unwrap();
+    }
+}
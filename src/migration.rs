@@ -0,0 +1,429 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    cipher::{self, Cipher, CipherError},
+    format::{self, FormatError},
+    kdf::{self, KdfError, KdfParams},
+    keyslot::{self, PASSWORD_SLOT_AD},
+    password::{Password, PasswordError},
+};
+
+/// What a migration step needs to re-encrypt a database
+pub struct MigrationContext<'a> {
+    pub master_password: &'a str,
+    // Used when a step introduces key derivation to a database that had none
+    pub kdf_params: KdfParams,
+}
+
+struct Migration {
+    from: u16,
+    apply: fn(&[u8], &MigrationContext) -> Result<Vec<u8>, MigrationError>,
+}
+
+// Each step takes the file contents of one version to the next, new format versions
+// are supported by adding a step to the end of the chain
+const MIGRATIONS: [Migration; 5] = [
+    Migration { from: 0, apply: v0_to_v1 },
+    Migration { from: 1, apply: v1_to_v2 },
+    Migration { from: 2, apply: v2_to_v3 },
+    Migration { from: 3, apply: v3_to_v4 },
+    Migration { from: 4, apply: v4_to_v5 },
+];
+
+/// Run every migration step needed to bring the file contents up to the current format
+pub fn migrate(contents: &[u8], context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let mut version = format::detect_version(contents)?;
+    let mut contents = contents.to_vec();
+
+    while version < format::FORMAT_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or(MigrationError::MissingStep(version))?;
+        contents = (migration.apply)(&contents, context)?;
+        version = format::detect_version(&contents)?;
+    }
+
+    Ok(contents)
+}
+
+// Version 0: a bare SHA-256 hash of the master password, with the raw master password
+// used as the magic-crypt key
+#[derive(Serialize, Deserialize)]
+struct DatabaseV0 {
+    master_password: Vec<u8>,
+    passwords: Vec<Password>,
+}
+
+// Version 1: an Argon2id verifier and magic-crypt encryption under the derived key
+#[derive(Serialize, Deserialize)]
+struct DatabaseV1 {
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    master_password: Vec<u8>,
+    passwords: Vec<Password>,
+}
+
+// Version 2: as version 1, but with passwords encrypted by XChaCha20-Poly1305
+#[derive(Serialize, Deserialize)]
+struct DatabaseV2 {
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    cipher: Cipher,
+    master_password: Vec<u8>,
+    passwords: Vec<Password>,
+}
+
+// Version 3: version 2 split into a binary framed header and a JSON body. Version 4 uses
+// the same body, but it may be sealed.
+#[derive(Serialize, Deserialize)]
+struct BodyV3 {
+    master_password: Vec<u8>,
+    passwords: Vec<Password>,
+}
+
+// Version 5: the verifier is replaced by key slots in the header, each wrapping a random
+// data key that the passwords are encrypted with
+#[derive(Serialize)]
+struct BodyV5 {
+    passwords: Vec<Password>,
+}
+
+fn v0_to_v1(contents: &[u8], context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let database: DatabaseV0 = serde_json::from_slice(contents)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(context.master_password.as_bytes());
+    if !kdf::constant_time_eq(&hasher.finalize(), &database.master_password) {
+        return Err(MigrationError::IncorrectPassword);
+    }
+
+    let salt = kdf::generate_salt();
+    let master_key = kdf::derive(context.master_password, &salt, &context.kdf_params)?;
+    let passwords = database.passwords
+        .iter()
+        .map(|password| Ok(password
+            .decrypt_legacy(context.master_password.as_bytes())?
+            .encrypt_legacy(&master_key.encryption_key)))
+        .collect::<Result<Vec<Password>, PasswordError>>()?;
+
+    Ok(serde_json::to_vec(&DatabaseV1 {
+        kdf: context.kdf_params,
+        salt,
+        master_password: master_key.verifier,
+        passwords,
+    })?)
+}
+
+fn v1_to_v2(contents: &[u8], context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let database: DatabaseV1 = serde_json::from_slice(contents)?;
+
+    let master_key = kdf::derive(context.master_password, &database.salt, &database.kdf)?;
+    if !kdf::constant_time_eq(&master_key.verifier, &database.master_password) {
+        return Err(MigrationError::IncorrectPassword);
+    }
+
+    let passwords = database.passwords
+        .iter()
+        .map(|password| password
+            .decrypt_legacy(&master_key.encryption_key)?
+            .encrypt(&master_key.encryption_key))
+        .collect::<Result<Vec<Password>, PasswordError>>()?;
+
+    Ok(serde_json::to_vec(&DatabaseV2 {
+        kdf: database.kdf,
+        salt: database.salt,
+        cipher: Cipher::XChaCha20Poly1305,
+        master_password: database.master_password,
+        passwords,
+    })?)
+}
+
+fn v2_to_v3(contents: &[u8], _context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let database: DatabaseV2 = serde_json::from_slice(contents)?;
+
+    let header = serde_json::json!({
+        "kdf": {
+            "id": "argon2id",
+            "memory_kib": database.kdf.memory_kib,
+            "iterations": database.kdf.iterations,
+            "parallelism": database.kdf.parallelism,
+            "salt": database.salt,
+        },
+        "cipher": database.cipher,
+    });
+    let body = serde_json::to_vec(&BodyV3 {
+        master_password: database.master_password,
+        passwords: database.passwords,
+    })?;
+
+    Ok(format::frame(3, &serde_json::to_vec(&header)?, &body))
+}
+
+// Version 4 adds the body layout and padding to the header, the body is unchanged
+fn v3_to_v4(contents: &[u8], _context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let (_, header, body) = format::split(contents)?;
+
+    let mut header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(header)?;
+    header.insert("layout".to_string(), serde_json::json!("entries"));
+    header.insert("padding".to_string(), serde_json::json!(0));
+
+    Ok(format::frame(4, &serde_json::to_vec(&header)?, body))
+}
+
+fn v4_to_v5(contents: &[u8], context: &MigrationContext) -> Result<Vec<u8>, MigrationError> {
+    let (_, header_serialized, body) = format::split(contents)?;
+    let mut header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(header_serialized)?;
+
+    let kdf_header = header.remove("kdf").unwrap_or_default();
+    let kdf_params: KdfParams = serde_json::from_value(kdf_header.clone())?;
+    let salt: Vec<u8> = serde_json::from_value(kdf_header["salt"].clone())?;
+    let master_key = kdf::derive(context.master_password, &salt, &kdf_params)?;
+
+    let sealed = header["layout"] == "sealed";
+    let body = if sealed {
+        let sealed_header = &contents[..contents.len() - body.len()];
+        match cipher::open(&master_key.encryption_key, body, sealed_header) {
+            Ok(body) => body,
+            Err(CipherError::AuthenticationFailure) => return Err(MigrationError::IncorrectPassword),
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        body.to_vec()
+    };
+
+    let database: BodyV3 = serde_json::from_slice(&body)?;
+    if !kdf::constant_time_eq(&master_key.verifier, &database.master_password) {
+        return Err(MigrationError::IncorrectPassword);
+    }
+
+    // The existing derived key becomes the key that wraps a new random data key, so the
+    // master password and its salt stay the same
+    let data_key = keyslot::generate_data_key();
+    let passwords = database.passwords
+        .iter()
+        .map(|password| password.update_encryption_key(&master_key.encryption_key, &data_key))
+        .collect::<Result<Vec<Password>, PasswordError>>()?;
+    header.insert("key_slots".to_string(), serde_json::json!([{
+        "kind": "password",
+        "kdf": kdf_header,
+        "wrapped_key": cipher::seal(&master_key.encryption_key, &data_key, PASSWORD_SLOT_AD)?,
+    }]));
+
+    let header_serialized = serde_json::to_vec(&header)?;
+    let mut body = serde_json::to_vec(&BodyV5 { passwords })?;
+    if sealed {
+        let padding = header["padding"].as_u64().unwrap_or_default() as u32;
+        format::pad(&mut body, padding);
+        body = cipher::seal(&data_key, &body, &format::frame(5, &header_serialized, &[]))?;
+    }
+
+    Ok(format::frame(5, &header_serialized, &body))
+}
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("the password entered was incorrect")]
+    IncorrectPassword,
+    #[error("no migration exists from database format version {0}")]
+    MissingStep(u16),
+    #[error("database could not be parsed for migration")]
+    Parse(#[from] serde_json::Error),
+    #[error("{0}")]
+    Format(#[from] FormatError),
+    #[error("failed to derive the master key")]
+    KeyDerivation(#[from] KdfError),
+    #[error("failed to re-encrypt a password")]
+    Password(#[from] PasswordError),
+    #[error("failed to re-encrypt the database")]
+    Cipher(#[from] CipherError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    fn context() -> MigrationContext<'static> {
+        MigrationContext { master_password: "testpass", kdf_params: TEST_PARAMS }
+    }
+
+    // A database as written by the first release of oxidizepw, holding the password
+    // testname / testuser / testpass
+    fn v0_database() -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(b"testpass");
+        let password = Password {
+            id: uuid::Uuid::nil(),
+            name: String::from("testname"),
+            username: String::from("testuser"),
+            password: String::from("testpass"),
+            ..Password::default()
+        }.encrypt_legacy(b"testpass");
+
+        serde_json::to_vec(&serde_json::json!({
+            "master_password": hasher.finalize().to_vec(),
+            "passwords": [{ "name": password.name, "username": password.username, "password": password.password }],
+        })).unwrap()
+    }
+
+    fn assert_decrypts(password: &Password, key: &[u8]) {
+        let decrypted = password.decrypt(key).unwrap();
+        assert_eq!(decrypted.name, "testname");
+        assert_eq!(decrypted.username, "testuser");
+        assert_eq!(decrypted.password, "testpass");
+    }
+
+    #[test]
+    fn migrate_v0_to_v1() {
+        let contents = v0_to_v1(&v0_database(), &context()).unwrap();
+        assert_eq!(format::detect_version(&contents).unwrap(), 1);
+
+        let database: DatabaseV1 = serde_json::from_slice(&contents).unwrap();
+        assert_eq!(database.kdf, TEST_PARAMS);
+        let master_key = kdf::derive("testpass", &database.salt, &database.kdf).unwrap();
+        assert_eq!(database.master_password, master_key.verifier);
+        let decrypted = database.passwords[0].decrypt_legacy(&master_key.encryption_key).unwrap();
+        assert_eq!(decrypted.password, "testpass");
+    }
+
+    #[test]
+    fn migrate_v0_to_v1_wrong_password() {
+        let context = MigrationContext { master_password: "wrongpass", kdf_params: TEST_PARAMS };
+        assert!(matches!(v0_to_v1(&v0_database(), &context), Err(MigrationError::IncorrectPassword)));
+    }
+
+    #[test]
+    fn migrate_v1_to_v2() {
+        let v1 = v0_to_v1(&v0_database(), &context()).unwrap();
+        let contents = v1_to_v2(&v1, &context()).unwrap();
+        assert_eq!(format::detect_version(&contents).unwrap(), 2);
+
+        let database: DatabaseV2 = serde_json::from_slice(&contents).unwrap();
+        assert_eq!(database.cipher, Cipher::XChaCha20Poly1305);
+        let master_key = kdf::derive("testpass", &database.salt, &database.kdf).unwrap();
+        assert_decrypts(&database.passwords[0], &master_key.encryption_key);
+    }
+
+    #[test]
+    fn migrate_v1_to_v2_wrong_password() {
+        let v1 = v0_to_v1(&v0_database(), &context()).unwrap();
+        let context = MigrationContext { master_password: "wrongpass", kdf_params: TEST_PARAMS };
+        assert!(matches!(v1_to_v2(&v1, &context), Err(MigrationError::IncorrectPassword)));
+    }
+
+    fn v3_database() -> Vec<u8> {
+        let v2 = v1_to_v2(&v0_to_v1(&v0_database(), &context()).unwrap(), &context()).unwrap();
+        v2_to_v3(&v2, &context()).unwrap()
+    }
+
+    #[test]
+    fn migrate_v2_to_v3() {
+        let contents = v3_database();
+        assert_eq!(format::detect_version(&contents).unwrap(), 3);
+
+        let (_, header, body) = format::split(&contents).unwrap();
+        let header: serde_json::Value = serde_json::from_slice(header).unwrap();
+        assert_eq!(header["kdf"]["id"], "argon2id");
+        assert_eq!(header["kdf"]["iterations"], TEST_PARAMS.iterations);
+        let kdf_params: KdfParams = serde_json::from_value(header["kdf"].clone()).unwrap();
+        let salt: Vec<u8> = serde_json::from_value(header["kdf"]["salt"].clone()).unwrap();
+
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let password: Password = serde_json::from_value(body["passwords"][0].clone()).unwrap();
+        let master_key = kdf::derive("testpass", &salt, &kdf_params).unwrap();
+        assert_decrypts(&password, &master_key.encryption_key);
+    }
+
+    #[test]
+    fn migrate_v3_to_v4() {
+        let v3 = v3_database();
+        let contents = v3_to_v4(&v3, &context()).unwrap();
+        assert_eq!(format::detect_version(&contents).unwrap(), 4);
+
+        let (_, header, body) = format::split(&contents).unwrap();
+        let header: serde_json::Value = serde_json::from_slice(header).unwrap();
+        assert_eq!(header["layout"], "entries");
+        assert_eq!(header["padding"], 0);
+        assert_eq!(body, format::split(&v3).unwrap().2);
+    }
+
+    fn v4_database(sealed: bool) -> Vec<u8> {
+        let v4 = v3_to_v4(&v3_database(), &context()).unwrap();
+        if !sealed {
+            return v4;
+        }
+
+        let (_, header, body) = format::split(&v4).unwrap();
+        let mut header: serde_json::Value = serde_json::from_slice(header).unwrap();
+        header["layout"] = serde_json::json!("sealed");
+        header["padding"] = serde_json::json!(1024);
+        let kdf_params: KdfParams = serde_json::from_value(header["kdf"].clone()).unwrap();
+        let salt: Vec<u8> = serde_json::from_value(header["kdf"]["salt"].clone()).unwrap();
+        let master_key = kdf::derive("testpass", &salt, &kdf_params).unwrap();
+
+        let header = serde_json::to_vec(&header).unwrap();
+        let sealed_body = cipher::seal(&master_key.encryption_key, body, &format::frame(4, &header, &[])).unwrap();
+        format::frame(4, &header, &sealed_body)
+    }
+
+    // Unwrap the data key from a version 5 file and return it with the body
+    fn open_v5(contents: &[u8]) -> (Vec<u8>, serde_json::Value) {
+        let (_, header_serialized, body) = format::split(contents).unwrap();
+        let header: serde_json::Value = serde_json::from_slice(header_serialized).unwrap();
+        assert!(header.get("kdf").is_none());
+        let slot: keyslot::KeySlot = serde_json::from_value(header["key_slots"][0].clone()).unwrap();
+        let data_key = slot.unwrap_password("testpass").unwrap().unwrap();
+
+        let body = if header["layout"] == "sealed" {
+            cipher::open(&data_key, body, &contents[..contents.len() - body.len()]).unwrap()
+        } else {
+            body.to_vec()
+        };
+        (data_key, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn migrate_v4_to_v5() {
+        let contents = v4_to_v5(&v4_database(false), &context()).unwrap();
+        assert_eq!(format::detect_version(&contents).unwrap(), 5);
+
+        let (data_key, body) = open_v5(&contents);
+        assert!(body.get("master_password").is_none());
+        let password: Password = serde_json::from_value(body["passwords"][0].clone()).unwrap();
+        assert_decrypts(&password, &data_key);
+    }
+
+    #[test]
+    fn migrate_v4_to_v5_sealed() {
+        let contents = v4_to_v5(&v4_database(true), &context()).unwrap();
+        let (_, _, body) = format::split(&contents).unwrap();
+        assert_eq!(body.len(), 1024 + cipher::NONCE_LEN + 16);
+
+        let (data_key, body) = open_v5(&contents);
+        let password: Password = serde_json::from_value(body["passwords"][0].clone()).unwrap();
+        assert_decrypts(&password, &data_key);
+    }
+
+    #[test]
+    fn migrate_v4_to_v5_wrong_password() {
+        let context = MigrationContext { master_password: "wrongpass", kdf_params: TEST_PARAMS };
+        assert!(matches!(v4_to_v5(&v4_database(false), &context), Err(MigrationError::IncorrectPassword)));
+        assert!(matches!(v4_to_v5(&v4_database(true), &context), Err(MigrationError::IncorrectPassword)));
+    }
+
+    #[test]
+    fn migrate_chain() {
+        let contents = migrate(&v0_database(), &context()).unwrap();
+        assert_eq!(format::detect_version(&contents).unwrap(), format::FORMAT_VERSION);
+    }
+
+    #[test]
+    fn migrate_current_is_unchanged() {
+        let current = format::encode(&format::Header::default(), b"{}").unwrap();
+        assert_eq!(migrate(&current, &context()).unwrap(), current);
+    }
+}
@@ -0,0 +1,385 @@
+//! One-time passwords for two factor authentication, HOTP from RFC 4226 and TOTP from
+//! RFC 6238. The seed is saved as an `otpauth://` URI, the format authenticator apps use in
+//! their QR codes, so HOTP counters are kept in the same place as the secret.
+
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use thiserror::Error;
+
+pub const DEFAULT_DIGITS: u32 = 6;
+pub const DEFAULT_PERIOD: u64 = 30;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+// Shorter secrets than this, 16 base32 characters, are too weak for authenticator apps to
+// hand out, so an argument that short is taken to be something else
+const MIN_SECRET_BYTES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtpKind {
+    // A new code every `period` seconds
+    Totp { period: u64 },
+    // A new code each time one is used, `counter` is the next one to use
+    Hotp { counter: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Otp {
+    pub secret: Vec<u8>,
+    pub kind: OtpKind,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    // The account label and issuer from the URI, kept so the URI can be given back unchanged
+    pub label: String,
+    pub issuer: Option<String>,
+}
+
+pub struct Code {
+    pub code: String,
+    // How long a TOTP code is still valid for
+    pub remaining_seconds: Option<u64>,
+    // The counter an HOTP code was made from
+    pub counter: Option<u64>,
+}
+
+impl Otp {
+    /// Read an `otpauth://totp/...` or `otpauth://hotp/...` URI, or a bare base32 secret which
+    /// is taken to be a TOTP seed with the usual settings
+    pub fn parse(input: &str) -> Result<Otp, OtpError> {
+        let input = input.trim();
+        let Some(rest) = input.strip_prefix("otpauth://") else {
+            return Ok(Otp {
+                secret: base32_decode(input)?,
+                kind: OtpKind::Totp { period: DEFAULT_PERIOD },
+                algorithm: Algorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                label: String::new(),
+                issuer: None,
+            });
+        };
+
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (kind, label) = path.split_once('/').unwrap_or((path, ""));
+        let mut secret = None;
+        let mut algorithm = Algorithm::Sha1;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter = None;
+        let mut issuer = None;
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value)?;
+            let invalid = || OtpError::InvalidParameter(key.to_string(), value.clone());
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(base32_decode(&value)?),
+                "algorithm" => algorithm = match value.to_ascii_uppercase().as_str() {
+                    "SHA1" => Algorithm::Sha1,
+                    "SHA256" => Algorithm::Sha256,
+                    "SHA512" => Algorithm::Sha512,
+                    _ => return Err(invalid()),
+                },
+                "digits" => digits = value.parse().map_err(|_| invalid())?,
+                "period" => period = value.parse().map_err(|_| invalid())?,
+                "counter" => counter = Some(value.parse().map_err(|_| invalid())?),
+                "issuer" => issuer = Some(value),
+                // Unknown parameters, like the image some apps add, don't affect the codes
+                _ => (),
+            }
+        }
+
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" if period > 0 => OtpKind::Totp { period },
+            "totp" => return Err(OtpError::InvalidParameter("period".to_string(), period.to_string())),
+            "hotp" => OtpKind::Hotp { counter: counter.ok_or(OtpError::MissingParameter("counter"))? },
+            _ => return Err(OtpError::UnknownType(kind.to_string())),
+        };
+        if !(6..=8).contains(&digits) {
+            return Err(OtpError::InvalidParameter("digits".to_string(), digits.to_string()));
+        }
+
+        Ok(Otp {
+            secret: secret.ok_or(OtpError::MissingParameter("secret"))?,
+            kind,
+            algorithm,
+            digits,
+            label: percent_decode(label)?,
+            issuer,
+        })
+    }
+
+    pub fn to_uri(&self) -> String {
+        let (kind, kind_parameter) = match self.kind {
+            OtpKind::Totp { period } => ("totp", format!("period={period}")),
+            OtpKind::Hotp { counter } => ("hotp", format!("counter={counter}")),
+        };
+        let mut uri = format!("otpauth://{kind}/{label}?secret={secret}&algorithm={algorithm}&digits={digits}&{kind_parameter}",
+            label = percent_encode(&self.label),
+            secret = base32_encode(&self.secret),
+            algorithm = self.algorithm,
+            digits = self.digits,
+        );
+        if let Some(issuer) = &self.issuer {
+            uri.push_str(&format!("&issuer={}", percent_encode(issuer)));
+        }
+        uri
+    }
+
+    /// The code for `unix_time`, an HOTP code moves the counter on to the next one
+    pub fn code(&mut self, unix_time: u64) -> Result<Code, OtpError> {
+        Ok(match &mut self.kind {
+            OtpKind::Totp { period } => Code {
+                code: totp(&self.secret, unix_time, *period, self.digits, self.algorithm),
+                remaining_seconds: Some(*period - unix_time % *period),
+                counter: None,
+            },
+            OtpKind::Hotp { counter } => {
+                let used = *counter;
+                *counter = used.checked_add(1).ok_or(OtpError::CounterExhausted)?;
+                Code { code: hotp(&self.secret, used, self.digits, self.algorithm), remaining_seconds: None, counter: Some(used) }
+            },
+        })
+    }
+}
+
+// Describes the settings without giving away the secret, for `get`
+impl fmt::Display for Otp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            OtpKind::Totp { period } => write!(f, "TOTP, {} digits every {period} seconds, {}", self.digits, self.algorithm),
+            OtpKind::Hotp { counter } => write!(f, "HOTP, {} digits, {}, counter {counter}", self.digits, self.algorithm),
+        }
+    }
+}
+
+fn mac(algorithm: Algorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    match algorithm {
+        Algorithm::Sha1 => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        },
+        Algorithm::Sha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        },
+        Algorithm::Sha512 => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        },
+    }
+}
+
+/// RFC 4226, the HMAC of the counter cut down to `digits` decimal digits
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> String {
+    let hash = mac(algorithm, secret, &counter.to_be_bytes());
+    // Dynamic truncation, the low four bits of the last byte pick where to read from
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    let code = u64::from(binary) % 10u64.pow(digits);
+    format!("{code:0width$}", width = digits as usize)
+}
+
+/// RFC 6238, HOTP with the number of periods since the Unix epoch as the counter
+pub fn totp(secret: &[u8], unix_time: u64, period: u64, digits: u32, algorithm: Algorithm) -> String {
+    hotp(secret, unix_time / period, digits, algorithm)
+}
+
+/// RFC 4648 base32, ignoring case, spaces and padding since secrets are often shown in
+/// lowercase groups of four
+/// Whether a command line argument looks like a seed, so it can be refused instead of being
+/// left where other users and shell history can see it
+pub fn looks_like_secret(arg: &str) -> bool {
+    arg.get(..10).is_some_and(|scheme| scheme.eq_ignore_ascii_case("otpauth://"))
+        || base32_decode(arg).is_ok_and(|secret| secret.len() >= MIN_SECRET_BYTES)
+}
+
+pub fn base32_decode(input: &str) -> Result<Vec<u8>, OtpError> {
+    let mut bytes = vec![];
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in input.chars().filter(|c| !c.is_whitespace() && *c != '-' && *c != '=') {
+        let Some(value) = BASE32_ALPHABET.iter().position(|&letter| letter as char == c.to_ascii_uppercase()) else {
+            return Err(OtpError::InvalidSecret);
+        };
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.is_empty() {
+        return Err(OtpError::InvalidSecret);
+    }
+    Ok(bytes)
+}
+
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let value = block.iter().fold(0u64, |value, &byte| (value << 8) | u64::from(byte));
+        let characters = (chunk.len() * 8).div_ceil(5);
+        for i in 0..8 {
+            if i < characters {
+                output.push(BASE32_ALPHABET[((value >> (35 - i * 5)) & 0x1f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+fn percent_decode(input: &str) -> Result<String, OtpError> {
+    let mut bytes = vec![];
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+            let Some(decoded) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) else {
+                return Err(OtpError::InvalidUri);
+            };
+            bytes.push(decoded);
+            rest = &tail[2..];
+        } else {
+            bytes.push(if byte == b'+' { b' ' } else { byte });
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| OtpError::InvalidUri)
+}
+
+fn percent_encode(input: &str) -> String {
+    input.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'@' => (byte as char).to_string(),
+        _ => format!("%{byte:02X}"),
+    }).collect()
+}
+
+#[derive(Error, Debug)]
+pub enum OtpError {
+    #[error("the one-time password secret is not valid base32")]
+    InvalidSecret,
+    #[error("the otpauth URI is not valid")]
+    InvalidUri,
+    #[error("the otpauth URI type `{0}` is not totp or hotp")]
+    UnknownType(String),
+    #[error("the otpauth URI has no `{0}`")]
+    MissingParameter(&'static str),
+    #[error("the otpauth URI `{0}` of \"{1}\" is not supported")]
+    InvalidParameter(String, String),
+    #[error("the HOTP counter has reached its largest value, set up a new one-time password with edit --otp")]
+    CounterExhausted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc4226_hotp() {
+        // Appendix D
+        let secret = b"12345678901234567890";
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp(secret, counter as u64, 6, Algorithm::Sha1), *code, "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn rfc6238_totp() {
+        // Appendix B, each algorithm has its own seed of the hash's length
+        let sha1 = b"12345678901234567890";
+        let sha256 = b"12345678901234567890123456789012";
+        let sha512 = b"1234567890123456789012345678901234567890123456789012345678901234";
+        let vectors: [(u64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1_code, sha256_code, sha512_code) in vectors {
+            assert_eq!(totp(sha1, time, 30, 8, Algorithm::Sha1), sha1_code, "SHA1 at {time}");
+            assert_eq!(totp(sha256, time, 30, 8, Algorithm::Sha256), sha256_code, "SHA256 at {time}");
+            assert_eq!(totp(sha512, time, 30, 8, Algorithm::Sha512), sha512_code, "SHA512 at {time}");
+        }
+    }
+
+    #[test]
+    fn rfc4648_base32() {
+        for (bytes, encoded) in [("f", "MY======"), ("fo", "MZXQ===="), ("foo", "MZXW6==="), ("foob", "MZXW6YQ="), ("fooba", "MZXW6YTB"), ("foobar", "MZXW6YTBOI======")] {
+            assert_eq!(base32_encode(bytes.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), bytes.as_bytes());
+        }
+        assert_eq!(base32_decode("mzxw 6ytb").unwrap(), b"fooba");
+        assert!(matches!(base32_decode("MZXW1"), Err(OtpError::InvalidSecret)));
+        assert!(matches!(base32_decode(""), Err(OtpError::InvalidSecret)));
+    }
+
+    #[test]
+    fn parse_uris() {
+        let otp = Otp::parse("otpauth://totp/Example%20Co:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example+Co&algorithm=SHA256&digits=8&period=60").unwrap();
+        assert_eq!(otp.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+        assert_eq!(otp.algorithm, Algorithm::Sha256);
+        assert_eq!(otp.digits, 8);
+        assert_eq!(otp.label, "Example Co:alice@example.com");
+        assert_eq!(otp.issuer.as_deref(), Some("Example Co"));
+        assert_eq!(Otp::parse(&otp.to_uri()).unwrap(), otp);
+
+        let otp = Otp::parse("JBSW Y3DP EHPK 3PXP").unwrap();
+        assert_eq!(otp.kind, OtpKind::Totp { period: DEFAULT_PERIOD });
+        assert_eq!(otp.digits, DEFAULT_DIGITS);
+
+        assert!(matches!(Otp::parse("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP"), Err(OtpError::MissingParameter("counter"))));
+        assert!(matches!(Otp::parse("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=9"), Err(OtpError::InvalidParameter(..))));
+        assert!(matches!(Otp::parse("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=0"), Err(OtpError::InvalidParameter(..))));
+        assert!(matches!(Otp::parse("otpauth://motp/x?secret=JBSWY3DPEHPK3PXP"), Err(OtpError::UnknownType(_))));
+
+        assert!(looks_like_secret("JBSW Y3DP EHPK 3PXP") && looks_like_secret("OTPAUTH://totp/x"));
+        assert!(!looks_like_secret("alice") && !looks_like_secret("alice@example.com"));
+    }
+
+    #[test]
+    fn codes() {
+        let mut otp = Otp::parse("otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1").unwrap();
+        let code = otp.code(0).unwrap();
+        assert_eq!((code.code.as_str(), code.counter), ("287082", Some(1)));
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 2 });
+
+        let mut otp = Otp::parse(&format!("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP&counter={}", u64::MAX)).unwrap();
+        assert!(matches!(otp.code(0), Err(OtpError::CounterExhausted)));
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: u64::MAX });
+
+        let mut otp = Otp::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        let code = otp.code(59).unwrap();
+        assert_eq!((code.code.as_str(), code.remaining_seconds), ("287082", Some(1)));
+    }
+}
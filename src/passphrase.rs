@@ -0,0 +1,163 @@
+//! Diceware passphrases built from the EFF wordlists, for passwords that have to be
+//! remembered and typed, like a database master password.
+//!
+//! The wordlists are by the Electronic Frontier Foundation, published under CC BY 3.0 US at
+//! <https://www.eff.org/deeplinks/2016/07/new-wordlists-random-passphrases>.
+
+use rand::{rngs::OsRng, Rng};
+use thiserror::Error;
+
+const EFF_LARGE_WORDLIST: &str = include_str!("wordlists/eff_large_wordlist.txt");
+const EFF_SHORT_WORDLIST: &str = include_str!("wordlists/eff_short_wordlist_1.txt");
+
+pub const DEFAULT_WORDS: usize = 6;
+pub const DEFAULT_SEPARATOR: &str = "-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Wordlist {
+    // 7776 words, one for each roll of five dice
+    #[default]
+    Large,
+    // 1296 shorter words, one for each roll of four dice, quicker to type
+    Short,
+}
+
+impl Wordlist {
+    pub fn words(self) -> Vec<&'static str> {
+        let list = match self {
+            Wordlist::Large => EFF_LARGE_WORDLIST,
+            Wordlist::Short => EFF_SHORT_WORDLIST,
+        };
+        // Each line is the dice roll and the word, separated by a tab
+        list.lines().filter_map(|line| line.split('\t').nth(1)).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassphraseOptions {
+    pub words: usize,
+    pub wordlist: Wordlist,
+    pub separator: String,
+    // Start every word with a capital letter, for sites that insist on one
+    pub capitalize: bool,
+    // Add a random digit to a random word, for sites that insist on one
+    pub digit: bool,
+}
+
+impl Default for PassphraseOptions {
+    fn default() -> Self {
+        PassphraseOptions {
+            words: DEFAULT_WORDS,
+            wordlist: Wordlist::default(),
+            separator: DEFAULT_SEPARATOR.to_string(),
+            capitalize: false,
+            digit: false,
+        }
+    }
+}
+
+pub struct Passphrase {
+    pub phrase: String,
+    // log2 of how many passphrases the same options could have made, the strength against
+    // someone who knows how it was generated
+    pub entropy_bits: f64,
+}
+
+/// Pick words uniformly at random from the operating system's random number generator
+pub fn generate(options: &PassphraseOptions) -> Result<Passphrase, PassphraseError> {
+    if options.words == 0 {
+        return Err(PassphraseError::NoWords);
+    }
+
+    let list = options.wordlist.words();
+    let mut words: Vec<String> = (0..options.words)
+        .map(|_| list[OsRng.gen_range(0..list.len())].to_string())
+        .collect();
+    let mut entropy_bits = options.words as f64 * (list.len() as f64).log2();
+
+    if options.capitalize {
+        words = words.iter().map(|word| capitalize(word)).collect();
+    }
+    if options.digit {
+        let word = OsRng.gen_range(0..words.len());
+        let digit = OsRng.gen_range(0..10);
+        words[word].push_str(&digit.to_string());
+        entropy_bits += (10.0 * words.len() as f64).log2();
+    }
+
+    Ok(Passphrase { phrase: words.join(&options.separator), entropy_bits })
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// A passphrase to offer when a new database is made
+pub fn suggest_master_password() -> Passphrase {
+    generate(&PassphraseOptions::default()).expect("the default options always make a passphrase")
+}
+
+#[derive(Error, Debug)]
+pub enum PassphraseError {
+    #[error("a passphrase needs at least one word")]
+    NoWords,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn wordlists() {
+        for (wordlist, length) in [(Wordlist::Large, 7776), (Wordlist::Short, 1296)] {
+            let words = wordlist.words();
+            assert_eq!(words.len(), length);
+            assert_eq!(words.iter().collect::<HashSet<_>>().len(), length, "{wordlist:?} has repeated words");
+        }
+        assert_eq!(Wordlist::Large.words()[0], "abacus");
+        assert_eq!(Wordlist::Short.words()[0], "acid");
+    }
+
+    #[test]
+    fn generate_default() {
+        let passphrase = generate(&PassphraseOptions::default()).unwrap();
+        assert!((passphrase.entropy_bits - 77.55).abs() < 0.01);
+
+        // Some words have hyphens of their own, so check the words with another separator
+        let options = PassphraseOptions { separator: " ".to_string(), ..PassphraseOptions::default() };
+        let passphrase = generate(&options).unwrap();
+        let large = Wordlist::Large.words();
+        let words: Vec<&str> = passphrase.phrase.split(' ').collect();
+        assert_eq!(words.len(), DEFAULT_WORDS);
+        assert!(words.iter().all(|word| large.contains(word)));
+    }
+
+    #[test]
+    fn generate_options() {
+        let options = PassphraseOptions {
+            words: 4,
+            wordlist: Wordlist::Short,
+            separator: " ".to_string(),
+            capitalize: true,
+            digit: true,
+        };
+        let passphrase = generate(&options).unwrap();
+        let words: Vec<&str> = passphrase.phrase.split(' ').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words.iter().all(|word| word.starts_with(|c: char| c.is_ascii_uppercase())));
+        assert_eq!(passphrase.phrase.chars().filter(|c| c.is_ascii_digit()).count(), 1);
+        // 4 words of 10.34 bits, plus a digit in one of 4 places
+        assert!((passphrase.entropy_bits - (4.0 * 1296f64.log2() + 40f64.log2())).abs() < 0.01);
+    }
+
+    #[test]
+    fn generate_no_words() {
+        assert!(matches!(generate(&PassphraseOptions { words: 0, ..PassphraseOptions::default() }), Err(PassphraseError::NoWords)));
+    }
+}
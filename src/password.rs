@@ -1,43 +1,497 @@
+use std::collections::BTreeMap;
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use magic_crypt::{new_magic_crypt, MagicCryptError, MagicCryptTrait};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+	cipher::{self, CipherError},
+	expiry,
+	field::FieldKind,
+	kind::EntryKind,
+};
+
+// How many hex digits of an id are shown by the list command
+pub const SHORT_ID_LEN: usize = 8;
+// How many previous passwords are kept for each entry, the oldest are dropped first
+pub const MAX_HISTORY: usize = 10;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct Password {
+    // Entries from before authenticated encryption have no id, they are given one when
+    // the database is migrated
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub name: String,
     pub username: String,
     pub password: String,
+    // The kind and details are only saved sealed together in `kind_details`, so the file
+    // doesn't show what kind an entry is or which details it has
+    #[serde(skip)]
+    pub kind: EntryKind,
+    // The details of kinds other than logins, see `EntryKind::details`
+    #[serde(skip)]
+    pub details: BTreeMap<String, String>,
+    // Only set on encrypted passwords. Entries saved before kinds don't have it, and are logins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind_details: Option<String>,
+    // RFC 3339 timestamps, encrypted like the other fields. Entries saved before they were
+    // recorded don't have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_changed: Option<String>,
+    // Set by the get command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_accessed: Option<String>,
+    // When the password was moved to the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<CustomField>,
+    // A path like `prod/aws`, see `folder::normalize_folder`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // An `otpauth://` URI, see `otp::Otp`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otp: Option<String>,
+    // A `YYYY-MM-DD` date the password has to be changed by, see `expiry`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    // How many days a password can be kept before it has to be changed again, as a whole
+    // number so it is encrypted like the other fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_days: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    // Passwords this one replaced, the most recent first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<PreviousPassword>,
+}
+
+// The name and value are encrypted, the kind is left readable but is bound to the value, see
+// `FieldKind::value_field`. The value is bound to its name as well, so values can't be moved
+// between fields of the same kind.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct CustomField {
+    pub name: String,
+    #[serde(default)]
+    pub kind: FieldKind,
+    pub value: String,
+}
+
+impl CustomField {
+	fn encrypt(&self, encryption_key: &[u8], id: &Uuid) -> Result<CustomField, PasswordError> {
+		Ok(CustomField {
+			name: encrypt_field(encryption_key, id, "fields.name", &self.name)?,
+			kind: self.kind,
+			value: encrypt_with(encryption_key, id, self.kind.value_field(), &self.name, &self.value)?,
+		})
+	}
+
+	fn decrypt(&self, decryption_key: &[u8], id: &Uuid) -> Result<CustomField, PasswordError> {
+		let name = decrypt_field(decryption_key, id, "fields.name", &self.name)?;
+		Ok(CustomField {
+			value: decrypt_with(decryption_key, id, self.kind.value_field(), &name, &self.value)?,
+			name,
+			kind: self.kind,
+		})
+	}
+}
+
+// A file kept with the entry in its own encrypted file, see `attachment`. The id and size are
+// left readable, the size of the file it is kept in gives it away anyway, but the name and
+// when it was added are bound to both so names can't be swapped between files.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub name: String,
+    pub size: u64,
+    pub added: String,
+}
+
+impl Attachment {
+	fn encrypt(&self, encryption_key: &[u8], id: &Uuid) -> Result<Attachment, PasswordError> {
+		let with = format!("{}/{}", self.id, self.size);
+		Ok(Attachment {
+			id: self.id,
+			name: encrypt_with(encryption_key, id, "attachments.name", &with, &self.name)?,
+			size: self.size,
+			added: encrypt_with(encryption_key, id, "attachments.added", &with, &self.added)?,
+		})
+	}
+
+	fn decrypt(&self, decryption_key: &[u8], id: &Uuid) -> Result<Attachment, PasswordError> {
+		let with = format!("{}/{}", self.id, self.size);
+		Ok(Attachment {
+			id: self.id,
+			name: decrypt_with(decryption_key, id, "attachments.name", &with, &self.name)?,
+			size: self.size,
+			added: decrypt_with(decryption_key, id, "attachments.added", &with, &self.added)?,
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct PreviousPassword {
+    pub password: String,
+    // When it was set, if that was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed: Option<String>,
+    // When it was replaced
+    pub replaced: String,
+}
+
+impl PreviousPassword {
+	// Encrypted with the id of the entry it belongs to, and the password with its place in the
+	// history and when it was replaced, so `restore` can't be given a different old password by
+	// reordering the history
+	fn encrypt(&self, encryption_key: &[u8], id: &Uuid, position: usize) -> Result<PreviousPassword, PasswordError> {
+		let with = format!("{position}/{}", self.replaced);
+		Ok(PreviousPassword {
+			password: encrypt_with(encryption_key, id, "history.password", &with, &self.password)?,
+			changed: self.changed
+				.as_deref()
+				.map(|changed| encrypt_with(encryption_key, id, "history.changed", &with, changed))
+				.transpose()?,
+			replaced: encrypt_field(encryption_key, id, "history.replaced", &self.replaced)?,
+		})
+	}
+
+	fn decrypt(&self, decryption_key: &[u8], id: &Uuid, position: usize) -> Result<PreviousPassword, PasswordError> {
+		let replaced = decrypt_field(decryption_key, id, "history.replaced", &self.replaced)?;
+		let with = format!("{position}/{replaced}");
+		Ok(PreviousPassword {
+			password: decrypt_with(decryption_key, id, "history.password", &with, &self.password)?,
+			changed: self.changed
+				.as_deref()
+				.map(|changed| decrypt_with(decryption_key, id, "history.changed", &with, changed))
+				.transpose()?,
+			replaced,
+		})
+	}
 }
 
 impl Password {
-	pub fn encrypt(&self, encryption_key: String) -> Self {
+	pub fn new(name: String, username: String, password: String) -> Self {
+		let now = now();
+		Password {
+			id: Uuid::new_v4(),
+			name,
+			username,
+			password,
+			kind: EntryKind::Login,
+			details: BTreeMap::new(),
+			kind_details: None,
+			created: Some(now.clone()),
+			modified: Some(now.clone()),
+			password_changed: Some(now),
+			last_accessed: None,
+			deleted: None,
+			notes: None,
+			urls: vec![],
+			fields: vec![],
+			folder: None,
+			tags: vec![],
+			otp: None,
+			expires: None,
+			rotate_days: None,
+			attachments: vec![],
+			history: vec![],
+		}
+	}
+
+	pub fn encrypt(&self, encryption_key: &[u8]) -> Result<Password, PasswordError> {
+		Ok(Password {
+			id: self.id,
+			name: encrypt_field(encryption_key, &self.id, self.kind.name_field(), &self.name)?,
+			username: encrypt_field(encryption_key, &self.id, "username", &self.username)?,
+			password: encrypt_field(encryption_key, &self.id, "password", &self.password)?,
+			kind: EntryKind::Login,
+			details: BTreeMap::new(),
+			kind_details: Some(encrypt_field(encryption_key, &self.id, "kind_details", &serde_json::json!(KindDetails {
+				kind: self.kind,
+				details: self.details.clone(),
+			}).to_string())?),
+			created: encrypt_optional(encryption_key, &self.id, "created", &self.created)?,
+			modified: encrypt_optional(encryption_key, &self.id, "modified", &self.modified)?,
+			password_changed: encrypt_optional(encryption_key, &self.id, "password_changed", &self.password_changed)?,
+			last_accessed: encrypt_optional(encryption_key, &self.id, "last_accessed", &self.last_accessed)?,
+			deleted: encrypt_optional(encryption_key, &self.id, "deleted", &self.deleted)?,
+			notes: encrypt_optional(encryption_key, &self.id, "notes", &self.notes)?,
+			urls: self.urls
+				.iter()
+				.map(|url| encrypt_field(encryption_key, &self.id, "urls", url))
+				.collect::<Result<_, _>>()?,
+			fields: self.fields
+				.iter()
+				.map(|field| field.encrypt(encryption_key, &self.id))
+				.collect::<Result<_, _>>()?,
+			folder: encrypt_optional(encryption_key, &self.id, "folder", &self.folder)?,
+			tags: self.tags
+				.iter()
+				.map(|tag| encrypt_field(encryption_key, &self.id, "tags", tag))
+				.collect::<Result<_, _>>()?,
+			otp: encrypt_optional(encryption_key, &self.id, "otp", &self.otp)?,
+			expires: encrypt_optional(encryption_key, &self.id, "expires", &self.expires)?,
+			rotate_days: encrypt_optional(encryption_key, &self.id, "rotate_days", &self.rotate_days)?,
+			attachments: self.attachments
+				.iter()
+				.map(|attachment| attachment.encrypt(encryption_key, &self.id))
+				.collect::<Result<_, _>>()?,
+			history: self.history
+				.iter()
+				.enumerate()
+				.map(|(position, previous)| previous.encrypt(encryption_key, &self.id, position))
+				.collect::<Result<_, _>>()?,
+		})
+	}
+
+	pub fn decrypt(&self, decryption_key: &[u8]) -> Result<Password, PasswordError> {
+		let KindDetails { kind, details } = match &self.kind_details {
+			Some(kind_details) => decrypt_kind_details(decryption_key, &self.id, kind_details)?,
+			None => KindDetails::default(),
+		};
+		Ok(Password {
+			id: self.id,
+			name: decrypt_field(decryption_key, &self.id, kind.name_field(), &self.name)?,
+			username: decrypt_field(decryption_key, &self.id, "username", &self.username)?,
+			password: decrypt_field(decryption_key, &self.id, "password", &self.password)?,
+			kind,
+			details,
+			kind_details: None,
+			created: decrypt_optional(decryption_key, &self.id, "created", &self.created)?,
+			modified: decrypt_optional(decryption_key, &self.id, "modified", &self.modified)?,
+			password_changed: decrypt_optional(decryption_key, &self.id, "password_changed", &self.password_changed)?,
+			last_accessed: decrypt_optional(decryption_key, &self.id, "last_accessed", &self.last_accessed)?,
+			deleted: decrypt_optional(decryption_key, &self.id, "deleted", &self.deleted)?,
+			notes: decrypt_optional(decryption_key, &self.id, "notes", &self.notes)?,
+			urls: self.urls
+				.iter()
+				.map(|url| decrypt_field(decryption_key, &self.id, "urls", url))
+				.collect::<Result<_, _>>()?,
+			fields: self.fields
+				.iter()
+				.map(|field| field.decrypt(decryption_key, &self.id))
+				.collect::<Result<_, _>>()?,
+			folder: decrypt_optional(decryption_key, &self.id, "folder", &self.folder)?,
+			tags: self.tags
+				.iter()
+				.map(|tag| decrypt_field(decryption_key, &self.id, "tags", tag))
+				.collect::<Result<_, _>>()?,
+			otp: decrypt_optional(decryption_key, &self.id, "otp", &self.otp)?,
+			expires: decrypt_optional(decryption_key, &self.id, "expires", &self.expires)?,
+			rotate_days: decrypt_optional(decryption_key, &self.id, "rotate_days", &self.rotate_days)?,
+			attachments: self.attachments
+				.iter()
+				.map(|attachment| attachment.decrypt(decryption_key, &self.id))
+				.collect::<Result<_, _>>()?,
+			history: self.history
+				.iter()
+				.enumerate()
+				.map(|(position, previous)| previous.decrypt(decryption_key, &self.id, position))
+				.collect::<Result<_, _>>()?,
+		})
+	}
+
+	// Passwords saved before authenticated encryption was added used magic-crypt, these are
+	// only needed to migrate those databases
+	pub fn encrypt_legacy(&self, encryption_key: &[u8]) -> Password {
 		let mc = new_magic_crypt!(encryption_key, 256);
 		Password {
+			id: self.id,
 			name: mc.encrypt_str_to_base64(&self.name),
 			username: mc.encrypt_str_to_base64(&self.username),
-			password: mc.encrypt_str_to_base64(&self.password)
+			password: mc.encrypt_str_to_base64(&self.password),
+			..Password::default()
 		}
 	}
 
-	pub fn decrypt(&self, decryption_key: &String) -> Result<Password, PasswordError>{
+	pub fn decrypt_legacy(&self, decryption_key: &[u8]) -> Result<Password, PasswordError> {
 		let mc = new_magic_crypt!(decryption_key, 256);
 		Ok(Password {
+			id: self.id,
 		    name: mc.decrypt_base64_to_string(&self.name)?,
 		    username: mc.decrypt_base64_to_string(&self.username)?,
 		    password: mc.decrypt_base64_to_string(&self.password)?,
+			..Password::default()
 		})
 	}
 
-	pub fn update_encryption_key(&self, current_key: &String, new_key: &String) -> Result<Password, PasswordError> {
-		let decrypted_password = self.decrypt(&current_key)?;
-		Ok(decrypted_password.encrypt(new_key.to_string()))
+	// Set a new password, recording when it changed and keeping the old one in the history
+	pub fn set_password(&mut self, password: String) {
+		let now = now();
+		if password != self.password {
+			let previous = std::mem::replace(&mut self.password, password);
+			self.history.insert(0, PreviousPassword {
+				password: previous,
+				changed: self.password_changed.take(),
+				replaced: now.clone(),
+			});
+			self.history.truncate(MAX_HISTORY);
+		}
+		self.password_changed = Some(now);
+	}
+
+	// Go back to the `number`th previous password, counting from 1 for the most recent. The
+	// current password takes its place in the history.
+	pub fn restore_previous(&mut self, number: usize) -> Option<()> {
+		if number == 0 || number > self.history.len() {
+			return None;
+		}
+		let previous = self.history.remove(number - 1);
+		self.set_password(previous.password);
+		Some(())
+	}
+
+	// The file itself is stored first, see `attachment::store`
+	pub fn add_attachment(&mut self, id: Uuid, name: String, size: u64) {
+		self.attachments.push(Attachment { id, name, size, added: now() });
+		self.touch_modified();
+	}
+
+	// Rotation counts from when the password was last changed, or was created if that
+	// wasn't recorded
+	pub fn due(&self) -> Option<DateTime<Utc>> {
+		let changed = self.password_changed.as_deref().or(self.created.as_deref());
+		let rotate_days = self.rotate_days.as_deref().and_then(|days| days.parse().ok());
+		expiry::due(self.expires.as_deref(), rotate_days, changed)
+	}
+
+	// Called on a decrypted password after any of its fields are changed
+	pub fn touch_modified(&mut self) {
+		self.modified = Some(now());
+	}
+
+	// Called on an encrypted password, only the access time is encrypted again
+	pub fn touch_accessed(&mut self, encryption_key: &[u8]) -> Result<(), PasswordError> {
+		self.last_accessed = Some(encrypt_field(encryption_key, &self.id, "last_accessed", &now())?);
+		Ok(())
+	}
+
+	// Called on an encrypted password as it is moved to the trash
+	pub fn mark_deleted(&mut self, encryption_key: &[u8]) -> Result<(), PasswordError> {
+		self.deleted = Some(encrypt_field(encryption_key, &self.id, "deleted", &now())?);
+		Ok(())
+	}
+
+	pub fn short_id(&self) -> String {
+		self.id.simple().to_string()[..SHORT_ID_LEN].to_string()
+	}
+
+	pub fn update_encryption_key(&self, current_key: &[u8], new_key: &[u8]) -> Result<Password, PasswordError> {
+		let decrypted_password = self.decrypt(current_key)?;
+		decrypted_password.encrypt(new_key)
+	}
+}
+
+fn now() -> String {
+	Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+	DateTime::parse_from_rfc3339(timestamp).ok().map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+// Timestamps are shown in local time, `missing` is shown for one that wasn't recorded
+pub fn format_timestamp(timestamp: &Option<String>, missing: &str) -> String {
+	match timestamp.as_deref().and_then(parse_timestamp) {
+		Some(timestamp) => format_time(timestamp),
+		None => missing.to_string(),
+	}
+}
+
+pub fn format_time(time: DateTime<Utc>) -> String {
+	time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+}
+
+// For due dates, shown in UTC so expiry dates read the same as they were given
+pub fn format_date(time: DateTime<Utc>) -> String {
+	time.format("%Y-%m-%d").to_string()
+}
+
+// Sealed as one value, so details can't be moved to another kind they don't belong to
+#[derive(Serialize, Deserialize, Default)]
+struct KindDetails {
+	kind: EntryKind,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	details: BTreeMap<String, String>,
+}
+
+fn decrypt_kind_details(key: &[u8], id: &Uuid, ciphertext: &str) -> Result<KindDetails, PasswordError> {
+	let kind_details: KindDetails = serde_json::from_str(&decrypt_field(key, id, "kind_details", ciphertext)?)
+		.map_err(|_| PasswordError::Malformed("kind_details"))?;
+	if kind_details.details.keys().any(|key| kind_details.kind.detail(key).is_none()) {
+		return Err(PasswordError::Malformed("kind_details"));
+	}
+	Ok(kind_details)
+}
+
+fn encrypt_optional(key: &[u8], id: &Uuid, field: &'static str, plaintext: &Option<String>) -> Result<Option<String>, PasswordError> {
+	plaintext.as_deref().map(|plaintext| encrypt_field(key, id, field, plaintext)).transpose()
+}
+
+fn decrypt_optional(key: &[u8], id: &Uuid, field: &'static str, ciphertext: &Option<String>) -> Result<Option<String>, PasswordError> {
+	ciphertext.as_deref().map(|ciphertext| decrypt_field(key, id, field, ciphertext)).transpose()
+}
+
+// Each field is bound to its entry id and field name through the associated data, so a
+// ciphertext moved to another field or entry fails to decrypt. Values that belong with
+// something else in the entry are bound to that too, after a separator no field name has.
+fn associated_data(id: &Uuid, field: &str, with: &str) -> Vec<u8> {
+	let mut associated_data = id.as_bytes().to_vec();
+	associated_data.extend_from_slice(field.as_bytes());
+	if !with.is_empty() {
+		associated_data.push(0);
+		associated_data.extend_from_slice(with.as_bytes());
 	}
+	associated_data
+}
+
+fn encrypt_field(key: &[u8], id: &Uuid, field: &'static str, plaintext: &str) -> Result<String, PasswordError> {
+	encrypt_with(key, id, field, "", plaintext)
+}
+
+fn decrypt_field(key: &[u8], id: &Uuid, field: &'static str, ciphertext: &str) -> Result<String, PasswordError> {
+	decrypt_with(key, id, field, "", ciphertext)
+}
+
+fn encrypt_with(key: &[u8], id: &Uuid, field: &'static str, with: &str, plaintext: &str) -> Result<String, PasswordError> {
+	let sealed = cipher::seal(key, plaintext.as_bytes(), &associated_data(id, field, with))?;
+	Ok(Base64::encode_string(&sealed))
+}
+
+fn decrypt_with(key: &[u8], id: &Uuid, field: &'static str, with: &str, ciphertext: &str) -> Result<String, PasswordError> {
+	let sealed = Base64::decode_vec(ciphertext).map_err(|_| PasswordError::Malformed(field))?;
+	let plaintext = match cipher::open(key, &sealed, &associated_data(id, field, with)) {
+		Ok(plaintext) => plaintext,
+		Err(CipherError::AuthenticationFailure) => return Err(PasswordError::AuthenticationFailure(field)),
+		Err(e) => return Err(e.into()),
+	};
+	String::from_utf8(plaintext).map_err(|_| PasswordError::Malformed(field))
 }
 
 #[derive(Error, Debug)]
 pub enum PasswordError {
     #[error("failed to decrypt password")]
     DecryptionFailure(#[from] MagicCryptError),
+    #[error("the `{0}` field failed authentication, the key is wrong or the entry has been tampered with")]
+    AuthenticationFailure(&'static str),
+    #[error("the `{0}` field is not valid encrypted data")]
+    Malformed(&'static str),
+    #[error("failed to encrypt password")]
+    EncryptionFailure(#[from] CipherError),
 }
 
 // Once custom errors have been made and implemented, further tests should be included
@@ -46,67 +500,282 @@ pub enum PasswordError {
 mod tests {
     use super::*;
 
+    const TEST_KEY: &[u8; 32] = b"testkeytestkeytestkeytestkeytest";
+    const NEW_KEY: &[u8; 32] = b"newkeynewkeynewkeynewkeynewkeyne";
+
     #[test]
     fn encrypt() {
-    	let password = Password {
-	        name: String::from("testname"),
-	        username: String::from("testuser"),
-	        password: String::from("testpass"),
-    	};
-    	let encrypted = password.encrypt("testkey".to_string());
-    	let encrypted_manual = Password {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let encrypted = password.encrypt(TEST_KEY).unwrap();
+    	let encrypted_again = password.encrypt(TEST_KEY).unwrap();
+    	assert_eq!(encrypted.id, password.id);
+    	assert_ne!(encrypted.name, password.name);
+    	// Fresh nonces mean equal plaintexts never produce equal ciphertexts
+    	assert_ne!(encrypted.name, encrypted_again.name);
+    	assert_ne!(encrypted.username, encrypted_again.username);
+    	assert_ne!(encrypted.password, encrypted_again.password);
+    }
+
+    #[test]
+    fn decrypt() {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_eq!(encrypted.decrypt(TEST_KEY).unwrap(), password);
+    }
+
+    #[test]
+    #[should_panic]
+    fn decrypt_wrong_key() {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_ne!(encrypted.decrypt(NEW_KEY).unwrap(), password);
+    }
+
+    #[test]
+    fn decrypt_swapped_fields() {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	std::mem::swap(&mut encrypted.name, &mut encrypted.password);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("name"))));
+    }
+
+    #[test]
+    fn decrypt_swapped_entries() {
+    	let first = Password::new(
+	        String::from("firstname"),
+	        String::from("firstuser"),
+	        String::from("firstpass"),
+    	).encrypt(TEST_KEY).unwrap();
+    	let mut second = Password::new(
+	        String::from("secondname"),
+	        String::from("seconduser"),
+	        String::from("secondpass"),
+    	).encrypt(TEST_KEY).unwrap();
+    	second.password = first.password;
+    	assert!(matches!(second.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("password"))));
+    }
+
+    #[test]
+    fn decrypt_modified() {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	let mut sealed = Base64::decode_vec(&encrypted.username).unwrap();
+    	sealed[cipher::NONCE_LEN] ^= 1;
+    	encrypted.username = Base64::encode_string(&sealed);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("username"))));
+    }
+
+    #[test]
+    fn decrypt_legacy() {
+    	let encrypted = Password {
+	        id: Uuid::nil(),
 	        name: "hGKSEIywJ6cjGJRAfvFziA==".to_string(),
 	        username: "GNdCbYuUh0TogMhvtE1uFQ==".to_string(),
 	        password: "idSbpqPWccMx79P/bRH3zw==".to_string(),
+	        ..Password::default()
 	    };
-    	assert_eq!(encrypted, encrypted_manual);
+    	let decrypted = encrypted.decrypt_legacy(b"testkey").unwrap();
+    	assert_eq!(decrypted.name, "testname");
+    	assert_eq!(decrypted.username, "testuser");
+    	assert_eq!(decrypted.password, "testpass");
     }
 
     #[test]
-    fn decrypt() {
-    	let password = Password {
-	        name: String::from("testname"),
-	        username: String::from("testuser"),
-	        password: String::from("testpass"),
-    	};
-    	let encrypted = password.encrypt("testkey".to_string());
-    	assert_eq!(encrypted.decrypt(&"testkey".to_string()).unwrap(), password);
+    fn timestamps_are_encrypted() {
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	assert!(password.created.is_some() && password.created == password.password_changed);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_ne!(encrypted.created, password.created);
+    	assert!(encrypted.last_accessed.is_none());
+
+    	encrypted.touch_accessed(TEST_KEY).unwrap();
+    	let decrypted = encrypted.decrypt(TEST_KEY).unwrap();
+    	assert_eq!(decrypted.created, password.created);
+    	assert!(parse_timestamp(decrypted.last_accessed.as_deref().unwrap()).is_some());
+
+    	// Timestamps are bound to their field like everything else
+    	encrypted.modified = encrypted.created.clone();
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("modified"))));
     }
 
     #[test]
-    #[should_panic]
-    fn decrypt_wrong_key() {
-    	let password = Password {
-	        name: String::from("testname"),
-	        username: String::from("testuser"),
-	        password: String::from("testpass"),
-    	};
-    	let encrypted = password.encrypt("testkey".to_string());
-    	assert_ne!(encrypted.decrypt(&"wrongkey".to_string()).unwrap(), password);
+    fn rotation_is_encrypted() {
+    	let mut password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	password.rotate_days = Some(String::from("90"));
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_ne!(encrypted.rotate_days, password.rotate_days);
+    	assert_eq!(encrypted.decrypt(TEST_KEY).unwrap().due(), password.due());
+
+    	// Changing the interval in the file fails instead of changing when the password is due
+    	encrypted.rotate_days = encrypted.created.clone();
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("rotate_days"))));
+    	encrypted.rotate_days = Some(String::from("36500"));
+    	assert!(encrypted.decrypt(TEST_KEY).is_err());
+    }
+
+    #[test]
+    fn custom_fields() {
+    	let mut password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	password.notes = Some(String::from("testnotes"));
+    	password.urls = vec![String::from("https://example.com")];
+    	password.fields = vec![
+    		CustomField { name: String::from("answer"), kind: FieldKind::Hidden, value: String::from("blue") },
+    		CustomField { name: String::from("recovery"), kind: FieldKind::Email, value: String::from("me@example.com") },
+    	];
+
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_ne!(encrypted.notes, password.notes);
+    	assert_ne!(encrypted.fields[0].value, "blue");
+    	assert_eq!(encrypted.decrypt(TEST_KEY).unwrap(), password);
+
+    	// A hidden field can't be made visible by changing its kind
+    	encrypted.fields[0].kind = FieldKind::Text;
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("fields.text"))));
+
+    	// Nor can two fields of the same kind trade values by swapping their names
+    	password.fields[1].kind = FieldKind::Hidden;
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	let name = encrypted.fields[0].name.clone();
+    	encrypted.fields[0].name = std::mem::replace(&mut encrypted.fields[1].name, name);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("fields.hidden"))));
+    }
+
+    #[test]
+    fn kind_details() {
+    	let mut password = Password::new(
+	        String::from("testname"),
+	        String::new(),
+	        String::new(),
+    	);
+    	password.kind = EntryKind::Card;
+    	password.details.insert(String::from("number"), String::from("4111111111111111"));
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	// Nothing in the file shows it is a card
+    	assert!(encrypted.kind.is_login() && encrypted.details.is_empty());
+    	assert!(!serde_json::json!(&encrypted).to_string().contains("number"));
+    	assert_eq!(encrypted.decrypt(TEST_KEY).unwrap(), password);
+
+    	// Dropping the kind would make it a login, which its name is bound against
+    	let login = Password::new(String::from("login"), String::new(), String::new()).encrypt(TEST_KEY).unwrap();
+    	encrypted.kind_details = None;
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("name"))));
+    	encrypted.kind_details = login.kind_details;
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("kind_details"))));
+    }
+
+    #[test]
+    fn attachments() {
+    	let mut password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	password.add_attachment(Uuid::new_v4(), String::from("codes.txt"), 10);
+    	password.add_attachment(Uuid::new_v4(), String::from("id_ed25519"), 10);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_eq!(encrypted.decrypt(TEST_KEY).unwrap(), password);
+
+    	// Swapping the files two names refer to fails, as does changing a size
+    	let id = encrypted.attachments[0].id;
+    	encrypted.attachments[0].id = std::mem::replace(&mut encrypted.attachments[1].id, id);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("attachments.name"))));
+    	encrypted.attachments[1].id = std::mem::replace(&mut encrypted.attachments[0].id, id);
+    	encrypted.attachments[0].size = 11;
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("attachments.name"))));
+    }
+
+    #[test]
+    fn password_history() {
+    	let mut password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("first"),
+    	);
+    	let first_changed = password.password_changed.clone();
+    	password.set_password(String::from("second"));
+    	password.set_password(String::from("second"));
+    	password.set_password(String::from("third"));
+    	let previous: Vec<&str> = password.history.iter().map(|previous| previous.password.as_str()).collect();
+    	assert_eq!(previous, vec!["second", "first"]);
+    	assert_eq!(password.history[1].changed, first_changed);
+
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	assert_ne!(encrypted.history[0].password, "second");
+    	let mut password = encrypted.decrypt(TEST_KEY).unwrap();
+
+    	// Old passwords can't be reordered in the file, with or without when they were replaced
+    	encrypted.history.swap(0, 1);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("history.password"))));
+    	let replaced = encrypted.history[0].replaced.clone();
+    	encrypted.history[0].replaced = std::mem::replace(&mut encrypted.history[1].replaced, replaced);
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure("history.password"))));
+
+    	assert!(password.restore_previous(3).is_none());
+    	password.restore_previous(2).unwrap();
+    	assert_eq!(password.password, "first");
+    	let previous: Vec<&str> = password.history.iter().map(|previous| previous.password.as_str()).collect();
+    	assert_eq!(previous, vec!["third", "second"]);
+
+    	for n in 0..MAX_HISTORY + 5 {
+    		password.set_password(n.to_string());
+    	}
+    	assert_eq!(password.history.len(), MAX_HISTORY);
+    	assert_eq!(password.history[0].password, (MAX_HISTORY + 3).to_string());
     }
 
     #[test]
     fn update_encryption_key() {
-    	let password = Password {
-	        name: String::from("testname"),
-	        username: String::from("testuser"),
-	        password: String::from("testpass"),
-    	};
-    	let mut encrypted = password.encrypt("testkey".to_string());
-    	encrypted = encrypted.update_encryption_key(&"testkey".to_string(), &"newkey".to_string()).unwrap();
-    	assert_eq!(encrypted.decrypt(&"newkey".to_string()).unwrap(), password);
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	encrypted = encrypted.update_encryption_key(TEST_KEY, NEW_KEY).unwrap();
+    	assert_eq!(encrypted.decrypt(NEW_KEY).unwrap(), password);
     }
 
     #[test]
-    #[should_panic] // TODO: replace with custom error types
     fn update_encryption_key_attempt_old_key() {
-    	let password = Password {
-	        name: String::from("testname"),
-	        username: String::from("testuser"),
-	        password: String::from("testpass"),
-    	};
-    	let mut encrypted = password.encrypt("testkey".to_string());
-    	encrypted = encrypted.update_encryption_key(&"testkey".to_string(), &"newkey".to_string()).unwrap();
-    	assert_eq!(encrypted.decrypt(&"testkey".to_string()).unwrap(), password);
+    	let password = Password::new(
+	        String::from("testname"),
+	        String::from("testuser"),
+	        String::from("testpass"),
+    	);
+    	let mut encrypted = password.encrypt(TEST_KEY).unwrap();
+    	encrypted = encrypted.update_encryption_key(TEST_KEY, NEW_KEY).unwrap();
+    	assert!(matches!(encrypted.decrypt(TEST_KEY), Err(PasswordError::AuthenticationFailure(_))));
     }
-}
\ No newline at end of file
+}
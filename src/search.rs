@@ -0,0 +1,114 @@
+/// How closely a query matches some text, best first. Only matches of the best kind found
+/// are kept, so an exact name is never drowned out by fuzzy matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    Exact,
+    // A prefix of the entry's id, which could also be part of a name like "cafe"
+    IdPrefix,
+    Prefix,
+    Substring,
+    Fuzzy,
+}
+
+// Shorter queries are too easy to turn into something else with a single typo
+const MIN_TYPO_LEN: usize = 3;
+// Queries up to this long may have one typo, longer ones one typo per this many characters
+const CHARS_PER_TYPO: usize = 4;
+
+/// Compare `query` against `text` ignoring case
+pub fn match_kind(query: &str, text: &str) -> Option<MatchKind> {
+    let query = query.to_lowercase();
+    let text = text.to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+
+    if text == query {
+        Some(MatchKind::Exact)
+    } else if text.starts_with(&query) {
+        Some(MatchKind::Prefix)
+    } else if text.contains(&query) {
+        Some(MatchKind::Substring)
+    } else if is_subsequence(&query, &text) || within_typos(&query, &text) {
+        Some(MatchKind::Fuzzy)
+    } else {
+        None
+    }
+}
+
+// Every character of the query appears in the text in order, "gthb" finds "github"
+fn is_subsequence(query: &str, text: &str) -> bool {
+    let mut text = text.chars();
+    query.chars().all(|q| text.any(|t| t == q))
+}
+
+// Small misspellings of the whole text, "githbu" finds "github"
+fn within_typos(query: &str, text: &str) -> bool {
+    let len = query.chars().count();
+    if len < MIN_TYPO_LEN {
+        return false;
+    }
+    let max_typos = (len / CHARS_PER_TYPO).max(1);
+    edit_distance(query, text) <= max_typos
+}
+
+// Optimal string alignment distance, so a swap of two neighbouring characters is one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_kinds() {
+        assert_eq!(match_kind("GitHub", "github"), Some(MatchKind::Exact));
+        assert_eq!(match_kind("git", "GitHub"), Some(MatchKind::Prefix));
+        assert_eq!(match_kind("hub", "github"), Some(MatchKind::Substring));
+        assert_eq!(match_kind("gthb", "github"), Some(MatchKind::Fuzzy));
+        assert_eq!(match_kind("githbu", "github"), Some(MatchKind::Fuzzy));
+        assert_eq!(match_kind("gitlab", "github"), None);
+        assert_eq!(match_kind("gx", "gh"), None);
+        assert_eq!(match_kind("gti", "git"), Some(MatchKind::Fuzzy));
+        assert_eq!(match_kind("", "github"), None);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("github", "github"), 0);
+        assert_eq!(edit_distance("githbu", "github"), 1);
+        assert_eq!(edit_distance("gitub", "github"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn match_kinds_order() {
+        assert!(MatchKind::Exact < MatchKind::IdPrefix && MatchKind::IdPrefix < MatchKind::Prefix);
+        assert!(MatchKind::Substring < MatchKind::Fuzzy);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use thiserror::Error;
+
+/// Where passwords come from. They are never taken from the command line, where they would
+/// be visible to other users and kept in shell history.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SecretSource {
+    #[default]
+    Prompt,
+    // One secret per line, in the order the command asks for them
+    Stdin,
+    Fd(u32),
+}
+
+enum Reader {
+    Prompt,
+    // Read through `io::stdin` each time rather than holding its lock, so other prompts
+    // can still read from it
+    Stdin,
+    Buffered(Box<dyn BufRead>),
+}
+
+pub struct Secrets {
+    reader: Reader,
+}
+
+impl Secrets {
+    pub fn open(source: SecretSource) -> Result<Secrets, SecretError> {
+        let reader = match source {
+            SecretSource::Prompt => Reader::Prompt,
+            SecretSource::Stdin => Reader::Stdin,
+            SecretSource::Fd(fd) => Reader::Buffered(Box::new(BufReader::new(open_fd(fd)?))),
+        };
+        Ok(Secrets { reader })
+    }
+
+    pub fn read(&mut self, prompt: &str) -> Result<String, SecretError> {
+        let mut line = String::new();
+        let read = match &mut self.reader {
+            Reader::Prompt => return Ok(rpassword::prompt_password(format!("{prompt}\n"))?),
+            Reader::Stdin => io::stdin().read_line(&mut line)?,
+            Reader::Buffered(reader) => reader.read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Err(SecretError::Exhausted);
+        }
+
+        let secret = line.strip_suffix('\n').unwrap_or(&line);
+        Ok(secret.strip_suffix('\r').unwrap_or(secret).to_string())
+    }
+
+    // A new secret is asked for twice when prompting, so a typo isn't saved unnoticed
+    pub fn read_new(&mut self, prompt: &str) -> Result<String, SecretError> {
+        let secret = self.read(prompt)?;
+        if matches!(self.reader, Reader::Prompt) && self.read("Please enter it again to confirm")? != secret {
+            return Err(SecretError::Mismatch);
+        }
+        Ok(secret)
+    }
+}
+
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<File, SecretError> {
+    Ok(File::open(format!("/dev/fd/{fd}"))?)
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: u32) -> Result<File, SecretError> {
+    Err(SecretError::Io(io::Error::new(io::ErrorKind::Unsupported, "reading passwords from a file descriptor needs a Unix system")))
+}
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("failed to read a password: {0}")]
+    Io(#[from] io::Error),
+    #[error("ran out of input while reading passwords, give one per line")]
+    Exhausted,
+    #[error("the passwords entered did not match")]
+    Mismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_input(input: &'static str) -> Secrets {
+        Secrets { reader: Reader::Buffered(Box::new(io::Cursor::new(input))) }
+    }
+
+    #[test]
+    fn read_lines_in_order() {
+        let mut secrets = from_input("first\nsecond\r\nthird");
+        assert_eq!(secrets.read("").unwrap(), "first");
+        assert_eq!(secrets.read_new("").unwrap(), "second");
+        assert_eq!(secrets.read("").unwrap(), "third");
+        assert!(matches!(secrets.read(""), Err(SecretError::Exhausted)));
+    }
+
+    #[test]
+    fn read_empty_line() {
+        let mut secrets = from_input("\n");
+        assert_eq!(secrets.read("").unwrap(), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_from_fd() {
+        use std::os::fd::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets");
+        std::fs::write(&path, "from fd\n").unwrap();
+        let file = File::open(&path).unwrap();
+
+        let mut secrets = Secrets::open(SecretSource::Fd(file.as_raw_fd() as u32)).unwrap();
+        assert_eq!(secrets.read("").unwrap(), "from fd");
+    }
+}
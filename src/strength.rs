@@ -0,0 +1,73 @@
+use std::fmt;
+
+use zxcvbn::zxcvbn;
+
+/// Scores run from 0 to 4, anything below this is too easy to guess
+pub const GOOD_SCORE: u8 = 3;
+pub const MAX_SCORE: u8 = 4;
+
+/// How hard a password is to guess, estimated by zxcvbn from the dictionary words, keyboard
+/// patterns, repeats, sequences, dates and l33t substitutions it is made of
+pub struct Strength {
+    pub score: u8,
+    // Only given for weak passwords
+    pub feedback: Option<String>,
+}
+
+impl Strength {
+    pub fn is_weak(&self) -> bool {
+        self.score < GOOD_SCORE
+    }
+}
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "score {} of {MAX_SCORE}", self.score)?;
+        if let Some(feedback) = &self.feedback {
+            write!(f, ". {feedback}")?;
+        }
+        Ok(())
+    }
+}
+
+/// `user_inputs` are words the password shouldn't be guessable from, like the name and
+/// username saved alongside it
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let entropy = zxcvbn(password, user_inputs);
+    Strength {
+        score: entropy.score().into(),
+        feedback: entropy
+            .feedback()
+            .map(|feedback| feedback.to_string().trim().to_string())
+            .filter(|feedback| !feedback.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_passwords_are_weak() {
+        for password in ["", "password", "qwertyuiop", "aaaaaaaa", "abcdef123", "19/07/1985", "p@ssw0rd"] {
+            let strength = estimate(password, &[]);
+            assert!(strength.is_weak(), "{password} scored {}", strength.score);
+            assert!(strength.feedback.is_some());
+        }
+    }
+
+    #[test]
+    fn random_passwords_are_strong() {
+        for password in ["k}`ZMz[T^7(_dq]E=_ge", "enforced-wilder-maker-tightrope-drudge-thrift"] {
+            let strength = estimate(password, &[]);
+            assert_eq!(strength.score, MAX_SCORE, "{password} scored {}", strength.score);
+            assert!(strength.feedback.is_none());
+        }
+    }
+
+    #[test]
+    fn user_inputs_are_guessable() {
+        let password = "gandalfthegrey";
+        assert!(estimate(password, &["gandalfthegrey"]).score < estimate(password, &[]).score);
+    }
+}
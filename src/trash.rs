@@ -0,0 +1,35 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::password;
+
+pub const DEFAULT_KEEP_DAYS: u32 = 30;
+// The longest entries can be kept for, a hundred years
+pub const MAX_KEEP_DAYS: u32 = 36500;
+
+/// When an entry deleted at `deleted` is removed from the trash for good, or `None` if that is
+/// past the latest date that can be represented
+pub fn expires(deleted: &str, keep_days: u32) -> Option<DateTime<Utc>> {
+    password::parse_timestamp(deleted).and_then(|deleted| deleted.checked_add_signed(Duration::days(keep_days.into())))
+}
+
+// Entries without a deletion time are kept until the trash is emptied
+pub fn is_expired(deleted: Option<&str>, keep_days: u32, now: DateTime<Utc>) -> bool {
+    deleted
+        .and_then(|deleted| expires(deleted, keep_days))
+        .is_some_and(|expires| expires <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let now = DateTime::parse_from_rfc3339("2021-01-31T00:00:00Z").unwrap().with_timezone(&Utc);
+        assert!(!is_expired(Some("2021-01-02T00:00:00Z"), 30, now));
+        assert!(is_expired(Some("2021-01-01T00:00:00Z"), 30, now));
+        assert!(is_expired(Some("2021-01-31T00:00:00Z"), 0, now));
+        assert!(!is_expired(None, 0, now));
+        assert!(!is_expired(Some("2021-01-01T00:00:00Z"), u32::MAX, now));
+    }
+}